@@ -1,21 +1,35 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::sync::Arc;
 
 use cargo::{Config, CliResult, CargoResult, ops};
+use cargo::ops::CompileOptions;
 use cargo::util::command_prelude::*;
-use cargo::core::{Shell, PackageId, Target, compiler::Executor};
+use cargo::core::{Shell, PackageId, Target, Workspace, compiler::{CompileKind, Executor, MessageFormat}};
+use cargo::core::dependency::DepKind;
+use cargo::core::profiles::Profiles;
 use cargo_util::ProcessBuilder;
 use anyhow::{Result, bail};
 
-struct LockCheckExecutor;
+struct LockCheckExecutor {
+    shared_graph_dir: PathBuf,
+    /// Arguments for lockcheck which are passed before the rustc arguments
+    lockcheck_args: Vec<String>,
+    sysroot: Option<String>,
+    /// Directory each crate's report is written to, as a file named after the crate
+    output_dir: Option<PathBuf>,
+    /// Extension of the report files, which depends on the report format
+    report_extension: &'static str,
+}
 
 impl Executor for LockCheckExecutor {
     fn exec(
         &self,
         cmd: &ProcessBuilder,
         _id: PackageId,
-        _target: &Target,
-        _mode: CompileMode,
+        target: &Target,
+        mode: CompileMode,
         on_stdout_line: &mut dyn FnMut(&str) -> CargoResult<()>,
         on_stderr_line: &mut dyn FnMut(&str) -> CargoResult<()>,
     ) -> CargoResult<()> {
@@ -23,17 +37,170 @@ impl Executor for LockCheckExecutor {
         let mut cmd = cmd.clone();
         cmd.program("lockcheck");
 
+        let mut args = self.lockcheck_args.iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        let mut shared_graph_dir = OsString::from("--shared-graph-dir=");
+        shared_graph_dir.push(&self.shared_graph_dir);
+        args.push(shared_graph_dir);
+        // crates are checked in parallel, so each one writes its own report
+        if let Some(output_dir) = &self.output_dir {
+            let report_name = format!(
+                "{}-{}{}.{}",
+                target.crate_name(),
+                target.kind().description(),
+                if mode.is_any_test() { "-test" } else { "" },
+                self.report_extension,
+            );
+            let mut output = OsString::from("--output=");
+            output.push(output_dir.join(report_name));
+            args.push(output);
+        }
+        args.push("--".into());
+        args.extend(cmd.get_args().cloned());
+        // cargo doesn't pass a sysroot, so this is the only one rustc sees
+        if let Some(sysroot) = &self.sysroot {
+            args.push("--sysroot".into());
+            args.push(sysroot.into());
+        }
+        cmd.args_replace(&args);
+
         cmd.exec_with_streaming(on_stdout_line, on_stderr_line, false)
             .map(drop)
     }
 }
 
+/// Collects the arguments passed to `cargo lockcheck` which are forwarded to lockcheck
+fn lockcheck_args(args: &ArgMatches) -> Vec<String> {
+    let mut out = Vec::new();
+
+    if let Some(min_confidence) = args.get_one::<String>("min-confidence") {
+        out.push("--min-confidence".to_owned());
+        out.push(min_confidence.clone());
+    }
+
+    if let Some(primary) = args.get_one::<String>("primary") {
+        out.push(format!("--primary={}", primary));
+    }
+
+    if let Some(changed_files) = args.get_one::<String>("changed-files") {
+        out.push(format!("--changed-files={}", changed_files));
+    }
+
+    if let Some(rustc) = args.get_one::<String>("rustc") {
+        out.push(format!("--rustc={}", rustc));
+    }
+
+    if let Some(format) = args.get_one::<String>("format") {
+        out.push(format!("--format={}", format));
+    }
+
+    if args.flag("stats") {
+        out.push("--stats".to_owned());
+    }
+
+    if args.flag("list-locks") {
+        out.push("--list-locks".to_owned());
+    }
+
+    if args.flag("progress") {
+        out.push("--progress".to_owned());
+    }
+
+    if args.flag("perf-hints") {
+        out.push("--perf-hints".to_owned());
+    }
+
+    if args.flag("entry-points") {
+        out.push("--entry-points".to_owned());
+    }
+
+    if args.flag("skipped-bodies") {
+        out.push("--skipped-bodies".to_owned());
+    }
+
+    if args.flag("untracked-guards") {
+        out.push("--untracked-guards".to_owned());
+    }
+
+    if args.flag("no-mir-opt") {
+        out.push("--no-mir-opt".to_owned());
+    }
+
+    if args.flag("check-config") {
+        out.push("--check-config".to_owned());
+    }
+
+    if args.flag("pessimistic") {
+        out.push("--pessimistic".to_owned());
+    }
+
+    if args.flag("cache") {
+        out.push("--cache".to_owned());
+    }
+
+    if let Some(explain) = args.get_many::<String>("explain") {
+        out.push("--explain".to_owned());
+        out.extend(explain.cloned());
+    }
+
+    out
+}
+
 /// This uses cargo to run lockcheck on all crates in the package
 fn run(config: &mut Config) -> CliResult {
     let args = Command::new("cargo_lockcheck")
-        .subcommand(subcommand("lockcheck"))
+        .subcommand(
+            subcommand("lockcheck")
+                .arg(opt("min-confidence", "Only report deadlocks with at least this confidence percentage").value_name("PERCENT"))
+                .arg(flag("tests", "Also analyse unit tests and integration tests"))
+                .arg(opt("message-format", "Error format").value_name("FMT").value_parser(["human", "short", "json"]))
+                .arg(opt("format", "Format of the findings written with `--output`, or printed to stdout for `json` and `sarif`").value_name("FMT").value_parser(["human", "short", "json", "sarif"]))
+                .arg(opt("output", "Directory to write a report to for each crate, instead of printing the findings").value_name("DIR"))
+                .arg(opt("sysroot", "Sysroot used for analysis, defaults to the sysroot of `RUSTC` if it is set").value_name("PATH"))
+                .arg(opt("rustc", "Rustc whose sysroot is used for analysis, instead of `RUSTC`").value_name("PATH"))
+                .arg(opt("primary", "Which lock call of a deadlock the diagnostic points to").value_name("first|second").value_parser(["first", "second"]))
+                .arg(opt("changed-files", "Only report findings involving these files, as a comma separated list relative to the workspace root").value_name("FILES"))
+                .arg(flag("stats", "Print the number of lock classes, invocations and dependancy edges found"))
+                .arg(flag("list-locks", "Print every place a lock is acquired"))
+                .arg(flag("progress", "Periodically print how much of each crate has been analysed"))
+                .arg(flag("perf-hints", "Note locks which are acquired on every iteration of a loop"))
+                .arg(flag("entry-points", "Note which entry points each deadlock is reachable from"))
+                .arg(flag("skipped-bodies", "Note every function which was not analysed because its mir could not be obtained"))
+                .arg(flag("untracked-guards", "Note every lock guard which stopped being followed because optimized mir reused its local"))
+                .arg(flag("no-mir-opt", "Disable mir optimizations and inlining, overriding `mir_opt_level` and `inline_mir` in the config"))
+                .arg(flag("no-build", "Don't run `cargo build` first, dependencies must already be built"))
+                .arg(flag("check-config", "Only check that every lock target in the config resolves"))
+                .arg(flag("pessimistic", "Assume functions which can't be analysed keep lock guards passed to them held"))
+                .arg(flag("cache", "Reuse the findings of the last run for crates which have not changed, cached findings are printed without source snippets or notes"))
+                .arg(flag("init", "Write a starter `lockcheck.toml` for the package instead of checking it"))
+                .arg(opt("explain", "Explain why a pair of lock classes is or is not a deadlock").num_args(2).value_names(["PARENT", "CHILD"]))
+        )
         .get_matches();
 
+    let lockcheck_args = args.subcommand_matches("lockcheck")
+        .map(lockcheck_args)
+        .unwrap_or_default();
+    let check_tests = args.subcommand_matches("lockcheck")
+        .map_or(false, |args| args.flag("tests"));
+    let sysroot = args.subcommand_matches("lockcheck")
+        .and_then(|args| args.get_one::<String>("sysroot"))
+        .cloned();
+    let message_format = args.subcommand_matches("lockcheck")
+        .and_then(|args| args.get_one::<String>("message-format"))
+        .cloned();
+    let no_build = args.subcommand_matches("lockcheck")
+        .map_or(false, |args| args.flag("no-build"));
+    let output_dir = args.subcommand_matches("lockcheck")
+        .and_then(|args| args.get_one::<String>("output"))
+        // lockcheck runs in the package directory, so the path must not be relative
+        .map(|output_dir| config.cwd().join(output_dir));
+    let report_extension = match args.subcommand_matches("lockcheck").and_then(|args| args.get_one::<String>("format")) {
+        Some(format) if format == "json" => "json",
+        Some(format) if format == "sarif" => "sarif",
+        _ => "txt",
+    };
+
     config.configure(
         0,
         false,
@@ -47,16 +214,68 @@ fn run(config: &mut Config) -> CliResult {
     )?;
 
     let workspace = args.workspace(config)?;
+    // in test mode cargo passes `--test` to rustc for every test target, which sets `cfg(test)`
+    let compile_mode = if check_tests {
+        CompileMode::Test
+    } else {
+        CompileMode::Build
+    };
+
     let mut compile_opts = args.compile_options(
         config,
-        CompileMode::Build,
+        compile_mode,
         Some(&workspace),
         ProfileChecking::Custom,
     )?;
+    if check_tests {
+        compile_opts.filter = CompileFilter::from_raw_arguments(
+            false,
+            Vec::new(),
+            false,
+            Vec::new(),
+            true, // all test targets
+            Vec::new(),
+            false,
+            Vec::new(),
+            false,
+            false,
+        );
+    }
+    if no_build {
+        check_dependencies_built(&workspace, &compile_opts, check_tests)?;
+    }
+    // cargo always asks rustc for json diagnostics, and lockcheck uses the same error format as rustc,
+    // so cargo can wrap lockcheck's diagnostics in its own json messages
+    match message_format.as_deref() {
+        Some("json") => compile_opts.build_config.message_format = MessageFormat::Json {
+            render_diagnostics: false,
+            short: false,
+            ansi: false,
+        },
+        Some("short") => compile_opts.build_config.message_format = MessageFormat::Short,
+        _ => (),
+    }
     // forces cargo to run lock check
     compile_opts.build_config.force_rebuild = true;
 
-    let executor: Arc<dyn Executor> = Arc::new(LockCheckExecutor);
+    // crates can be checked in parallel, since cargo always checks a crate after its dependencies,
+    // so each crate sees the lock dependancy edges of every crate it is linked with,
+    // edges left over from the previous run are cleared first
+    let shared_graph_dir = workspace.target_dir().join("lockcheck").into_path_unlocked();
+    if shared_graph_dir.exists() {
+        cargo_util::paths::remove_dir_all(&shared_graph_dir)?;
+    }
+    if let Some(output_dir) = &output_dir {
+        cargo_util::paths::create_dir_all(output_dir)?;
+    }
+
+    let executor: Arc<dyn Executor> = Arc::new(LockCheckExecutor {
+        shared_graph_dir,
+        lockcheck_args,
+        sysroot,
+        output_dir,
+        report_extension,
+    });
 
     ops::compile_with_exec(
         &workspace,
@@ -67,6 +286,88 @@ fn run(config: &mut Config) -> CliResult {
     Ok(())
 }
 
+/// Checks the metadata of every dependency of the workspace exists in the output directory of the selected profile
+///
+/// Without a build the mir of dependencies only exists if they were built before
+fn check_dependencies_built(workspace: &Workspace<'_>, compile_opts: &CompileOptions, check_tests: bool) -> Result<()> {
+    let build_config = &compile_opts.build_config;
+    let profile_dir = Profiles::new(workspace, build_config.requested_profile)?.get_dir_name();
+    let mut build_dir = workspace.target_dir().into_path_unlocked();
+    if let CompileKind::Target(target) = build_config.single_requested_kind()? {
+        build_dir.push(target.short_name());
+    }
+    build_dir.push(profile_dir.as_str());
+
+    let deps_dir = build_dir.join("deps");
+    if !deps_dir.exists() {
+        bail!("`--no-build` was passed, but `{}` does not exist, run `cargo build` first", deps_dir.display());
+    }
+
+    let (packages, resolve) = ops::resolve_ws(workspace)?;
+    let mut missing = Vec::new();
+    for member in workspace.members() {
+        for (dep_id, deps) in resolve.deps(member.package_id()) {
+            let used = deps.iter().any(|dep| {
+                !dep.is_optional() && (dep.kind() == DepKind::Normal || (check_tests && dep.kind() == DepKind::Development))
+            });
+            // other workspace members are analysed by lockcheck in this run
+            if !used || workspace.members().any(|member| member.package_id() == dep_id) {
+                continue;
+            }
+
+            let package = packages.get_one(dep_id)?;
+            // proc macros are only run by the compiler, so they have no metadata
+            let Some(lib) = package.targets().iter().find(|target| target.is_lib() && !target.is_proc_macro()) else {
+                continue;
+            };
+
+            if !has_rmeta(&deps_dir, &lib.crate_name())? {
+                missing.push(dep_id.name().to_string());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        bail!(
+            "`--no-build` was passed, but these dependencies are not built in `{}`: {}, run `cargo build` first",
+            build_dir.display(),
+            missing.join(", "),
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns true if an `.rmeta` file for the crate is in the directory
+fn has_rmeta(deps_dir: &Path, crate_name: &str) -> Result<bool> {
+    let prefix = format!("lib{}-", crate_name);
+
+    for entry in std::fs::read_dir(deps_dir)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with(&prefix) && file_name.ends_with(".rmeta") {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Runs `lockcheck --init` to write a starter config
+fn run_init() -> Result<()> {
+    let init_status = process::Command::new("lockcheck")
+        .arg("--init")
+        .status()?;
+
+    if !init_status.success() {
+        bail!("Writing config failed");
+    }
+
+    Ok(())
+}
+
 /// Runs cargo build
 /// 
 /// This is needed because lockcheck needs the mir of dependancies to be generated
@@ -83,9 +384,22 @@ fn run_cargo_build() -> Result<()> {
 }
 
 fn main() {
-    if let Err(err) = run_cargo_build() {
-        println!("{:?}", err);
-        process::exit(1);
+    // nothing is built or checked when only writing the config
+    if std::env::args().any(|arg| arg == "--init") {
+        if let Err(err) = run_init() {
+            println!("{:?}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // this is checked before the arguments are parsed, since the build must finish before cargo is configured
+    let no_build = std::env::args().any(|arg| arg == "--no-build");
+    if !no_build {
+        if let Err(err) = run_cargo_build() {
+            println!("{:?}", err);
+            process::exit(1);
+        }
     }
 
     let mut config = match Config::default() {
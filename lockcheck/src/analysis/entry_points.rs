@@ -0,0 +1,194 @@
+use std::collections::{HashMap, HashSet};
+
+use rustc_span::{Span, symbol::sym, def_id::DefId};
+use rustc_middle::ty::{TyCtxt, TyKind};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_middle::mir::{Body, TerminatorKind, StatementKind, Rvalue, AggregateKind, Operand};
+
+use super::errors::ErrorNote;
+use crate::config::path_matches;
+use crate::tyctxt_ext::TyCtxtExt;
+
+/// Functions which run the closure or function passed to them on a new thread
+const THREAD_SPAWN_FUNCTIONS: &[&str] = &[
+    "std::thread::spawn",
+    "std::thread::Builder::spawn",
+    "std::thread::Builder::spawn_scoped",
+    "std::thread::Scope::spawn",
+];
+
+/// A place where execution of the crate's code starts
+enum EntryPoint {
+    Main,
+    /// A test function with the given name
+    Test(String),
+    /// A thread spawned at the given span
+    Thread(Span),
+}
+
+/// Which entry points each function in the crate can be reached from, following direct calls and closures
+pub struct EntryPoints<'tcx> {
+    tcx: TyCtxt<'tcx>,
+    entry_points: Vec<EntryPoint>,
+    /// Indexes into `entry_points` for every reachable function
+    reachable_from: HashMap<DefId, Vec<usize>>,
+}
+
+impl<'tcx> EntryPoints<'tcx> {
+    /// Finds the entry points and builds the call graph of the given bodies
+    pub fn new(tcx: TyCtxt<'tcx>, bodies: &[DefId]) -> Self {
+        let mut entry_points = Vec::new();
+        let mut roots = Vec::new();
+
+        if let Some((main_def_id, _)) = tcx.entry_fn(()) {
+            entry_points.push(EntryPoint::Main);
+            roots.push(main_def_id);
+        }
+
+        let mut call_graph = HashMap::new();
+        for def_id in bodies.iter().copied() {
+            let Some(mir_body) = tcx.try_optimized_mir(def_id) else {
+                continue;
+            };
+
+            // the test harness generates a constant marked with the test's name, which calls the test function
+            if let Some(test_name) = tcx.get_attr(def_id, sym::rustc_test_marker).and_then(|attr| attr.value_str()) {
+                entry_points.push(EntryPoint::Test(test_name.to_string()));
+                roots.push(def_id);
+            }
+
+            for (spawn_span, thread_fn) in thread_spawns(tcx, mir_body) {
+                entry_points.push(EntryPoint::Thread(spawn_span));
+                roots.push(thread_fn);
+            }
+
+            call_graph.insert(def_id, callees(tcx, mir_body));
+        }
+
+        let mut reachable_from = HashMap::<DefId, Vec<usize>>::new();
+        for (index, root) in roots.into_iter().enumerate() {
+            let mut visited = HashSet::new();
+            let mut stack = vec![root];
+
+            while let Some(def_id) = stack.pop() {
+                if !visited.insert(def_id) {
+                    continue;
+                }
+
+                reachable_from.entry(def_id).or_default().push(index);
+                if let Some(callees) = call_graph.get(&def_id) {
+                    stack.extend(callees.iter().copied());
+                }
+            }
+        }
+
+        EntryPoints {
+            tcx,
+            entry_points,
+            reachable_from,
+        }
+    }
+
+    /// Creates a note listing the entry points any of the functions can be reached from
+    pub fn note(&self, functions: &[DefId]) -> ErrorNote {
+        let mut indexes = functions.iter()
+            .filter_map(|def_id| self.reachable_from.get(def_id))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        indexes.sort();
+        indexes.dedup();
+
+        if indexes.is_empty() {
+            return ErrorNote::new("not reachable from `main`, a test or a spawned thread through direct calls".to_owned());
+        }
+
+        let descriptions = indexes.into_iter()
+            .map(|index| self.describe(&self.entry_points[index]))
+            .collect::<Vec<_>>();
+
+        ErrorNote::new(format!("reachable from {}", join_list(&descriptions)))
+    }
+
+    fn describe(&self, entry_point: &EntryPoint) -> String {
+        match entry_point {
+            EntryPoint::Main => "`main`".to_owned(),
+            EntryPoint::Test(name) => format!("test `{}`", name),
+            EntryPoint::Thread(span) => {
+                let location = self.tcx.sess.source_map().lookup_char_pos(span.lo());
+                format!("thread spawned at {}:{}", location.file.name.prefer_local(), location.line)
+            },
+        }
+    }
+}
+
+/// Returns the closures and functions which are called, created or passed as a value in the body
+fn callees<'tcx>(tcx: TyCtxt<'tcx>, mir_body: &Body<'tcx>) -> Vec<DefId> {
+    let mut out = Vec::new();
+
+    for block_data in mir_body.basic_blocks.iter() {
+        for statement in block_data.statements.iter() {
+            let StatementKind::Assign(assign_data) = &statement.kind else {
+                continue;
+            };
+
+            match &assign_data.1 {
+                Rvalue::Aggregate(aggregate_kind, _) => match **aggregate_kind {
+                    AggregateKind::Closure(def_id, _) | AggregateKind::Generator(def_id, _, _) => out.push(def_id),
+                    _ => (),
+                },
+                Rvalue::Use(operand) | Rvalue::Cast(_, operand, _) => out.extend(fn_item(tcx, mir_body, operand)),
+                _ => (),
+            }
+        }
+
+        if let TerminatorKind::Call { func, args, .. } = &block_data.terminator().kind {
+            out.extend(fn_item(tcx, mir_body, func));
+            out.extend(args.iter().filter_map(|arg| fn_item(tcx, mir_body, arg)));
+        }
+    }
+
+    out
+}
+
+/// Returns the function or closure an operand refers to, if it is a function item or closure
+fn fn_item<'tcx>(tcx: TyCtxt<'tcx>, mir_body: &Body<'tcx>, operand: &Operand<'tcx>) -> Option<DefId> {
+    match operand.ty(&mir_body.local_decls, tcx).peel_refs().kind() {
+        TyKind::FnDef(def_id, _) | TyKind::Closure(def_id, _) => Some(*def_id),
+        _ => None,
+    }
+}
+
+/// Returns the span of every call spawning a thread in the body, along with the function the thread runs
+fn thread_spawns<'tcx>(tcx: TyCtxt<'tcx>, mir_body: &Body<'tcx>) -> Vec<(Span, DefId)> {
+    let mut out = Vec::new();
+
+    for block_data in mir_body.basic_blocks.iter() {
+        let terminator = block_data.terminator();
+        let TerminatorKind::Call { func, args, fn_span, .. } = &terminator.kind else {
+            continue;
+        };
+
+        let Some(spawn_fn) = fn_item(tcx, mir_body, func) else {
+            continue;
+        };
+
+        let spawn_path = with_no_trimmed_paths!(tcx.def_path_str(spawn_fn));
+        if !THREAD_SPAWN_FUNCTIONS.iter().any(|function| path_matches(function, &spawn_path)) {
+            continue;
+        }
+
+        out.extend(args.iter().filter_map(|arg| fn_item(tcx, mir_body, arg)).map(|thread_fn| (*fn_span, thread_fn)));
+    }
+
+    out
+}
+
+/// Joins items into a list such as `a, b and c`
+fn join_list(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
\ No newline at end of file
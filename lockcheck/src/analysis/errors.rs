@@ -1,11 +1,21 @@
-use std::cell::RefCell;
-use std::collections::BTreeSet;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, BTreeMap};
 use std::rc::Rc;
+use std::path::PathBuf;
 
 use rustc_session::Session;
 use rustc_middle::ty::Ty;
-use rustc_span::Span;
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_span::{Span, DUMMY_SP};
+use rustc_span::def_id::{DefId, DefIndex, CrateNum};
 use rustc_error_messages::MultiSpan;
+use rustc_errors::{Diagnostic, DiagnosticId};
+use serde::{Serialize, Deserialize};
+
+use crate::args::{OutputFormat, PrimarySpan};
+use crate::config::{FailOn, Severity, workspace_root};
+use crate::tyctxt_ext::MissingMir;
+use crate::report;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorStatus {
@@ -23,55 +33,634 @@ pub struct Errors<'tcx> {
     session: Rc<Session>,
     // this ensures errors are emitted in order
     errors: RefCell<BTreeSet<DeadlockError<'tcx>>>,
+    /// Warnings for blocking calls, keyed by the span of the call so each call is reported once
+    blocking_call_warnings: RefCell<BTreeMap<Span, BlockingCallWarning<'tcx>>>,
+    /// Errors with a lower confidence than this are not reported
+    min_confidence: u8,
+    /// Which deadlocks are emitted as errors, the rest are emitted as warnings
+    fail_on: FailOn,
+    format: OutputFormat,
+    /// Set if findings are only recorded, because they are written as a report once analysis finishes
+    report: bool,
+    /// Which lock call of a deadlock the main span of its diagnostic points to
+    primary: PrimarySpan,
+    /// Locks acquired on every iteration of a loop, only collected with `--perf-hints`
+    perf_hints: RefCell<BTreeMap<Span, PerfHint<'tcx>>>,
+    /// Paths of bodies which were skipped because their mir could not be obtained, only collected with `--skipped-bodies`
+    skipped_bodies: RefCell<Vec<(String, MissingMir)>>,
+    /// Spans where a lock guard stopped being followed and why, only collected with `--untracked-guards`
+    untracked_guards: RefCell<Vec<(Span, &'static str)>>,
+    /// Number of findings which were not reported, keyed by what suppressed them
+    suppressed: RefCell<BTreeMap<&'static str, usize>>,
+    /// Deadlocks which were not reported, keyed like `errors` so each is only counted once in `suppressed`
+    suppressed_errors: RefCell<BTreeMap<DeadlockError<'tcx>, &'static str>>,
+    /// Set if no lock invocations were found, so nothing was checked
+    no_invocations: Cell<bool>,
+    /// Every diagnostic emitted by `emit_all_errors`
+    findings: RefCell<Vec<Finding>>,
+    /// If set, only findings involving one of these files are reported
+    changed_files: Option<Vec<String>>,
+    /// Paths in findings are relative to this directory, so reports are the same wherever the crate is checked out
+    workspace_root: Option<PathBuf>,
 }
 
 impl<'tcx> Errors<'tcx> {
-    pub fn new(session: Rc<Session>) -> Self {
+    pub fn new(session: Rc<Session>, min_confidence: u8, fail_on: FailOn, format: OutputFormat, report: bool, primary: PrimarySpan, changed_files: Option<Vec<String>>) -> Self {
         Errors {
             session,
             errors: RefCell::default(),
+            blocking_call_warnings: RefCell::default(),
+            perf_hints: RefCell::default(),
+            skipped_bodies: RefCell::default(),
+            untracked_guards: RefCell::default(),
+            suppressed: RefCell::default(),
+            suppressed_errors: RefCell::default(),
+            min_confidence,
+            fail_on,
+            format,
+            report,
+            primary,
+            no_invocations: Cell::new(false),
+            findings: RefCell::default(),
+            changed_files,
+            workspace_root: workspace_root(),
         }
     }
 
-    pub fn emit_deadlock_error(&self, parent_invocation: InvocationErrorInfo<'tcx>, child_invocation: InvocationErrorInfo<'tcx>) {
-        let error = DeadlockError {
-            parent_invocation,
-            child_invocation,
-        };
+    /// Warns that none of the configured lock methods were called, which usually means the config is wrong
+    pub fn emit_no_invocations_warning(&self) {
+        self.no_invocations.set(true);
+    }
+
+    /// Returns false if the error is not reported because of `--min-confidence` or `--changed-files`
+    pub fn emit_deadlock_error(&self, error: DeadlockError<'tcx>) -> bool {
+        if error.confidence < self.min_confidence {
+            self.suppressed_errors.borrow_mut().entry(error).or_insert("min_confidence");
+            return false;
+        }
+
+        if !self.involves_changed_file(&[error.parent_invocation.callsite, error.child_invocation.callsite]) {
+            self.suppressed_errors.borrow_mut().entry(error).or_insert("changed_files");
+            return false;
+        }
 
         self.errors.borrow_mut().insert(error);
+        true
+    }
+
+    pub fn emit_blocking_call_warning(&self, warning: BlockingCallWarning<'tcx>) {
+        if !self.involves_changed_file(&[warning.invocation.callsite, warning.call_span]) {
+            return;
+        }
+
+        self.blocking_call_warnings.borrow_mut().entry(warning.call_span).or_insert(warning);
+    }
+
+    /// Counts a finding which was not reported because of `mechanism`, such as `safe_pairs`
+    pub fn record_suppressed(&self, mechanism: &'static str) {
+        *self.suppressed.borrow_mut().entry(mechanism).or_default() += 1;
+    }
+
+    pub fn emit_perf_hint(&self, hint: PerfHint<'tcx>) {
+        if !self.involves_changed_file(&[hint.invocation.callsite]) {
+            return;
+        }
+
+        self.perf_hints.borrow_mut().entry(hint.invocation.span).or_insert(hint);
+    }
+
+    pub fn emit_skipped_body(&self, path: String, reason: MissingMir) {
+        self.skipped_bodies.borrow_mut().push((path, reason));
+    }
+
+    pub fn emit_untracked_guard(&self, span: Span, reason: &'static str) {
+        self.untracked_guards.borrow_mut().push((span, reason));
     }
 
     pub fn emit_all_errors(&self) -> ErrorStatus {
-        for error in self.errors.borrow().iter() {
-            let mut multi_span = MultiSpan::from_span(error.child_invocation.span);
-            multi_span.push_span_label(error.parent_invocation.span, format!("lock class `{}` first locked here", error.parent_invocation.ty));
-            multi_span.push_span_label(error.child_invocation.span, format!("deadlock occurs when lock class `{}` locked here", error.child_invocation.ty));
+        let mut status = ErrorStatus::Ok;
+
+        if self.no_invocations.get() {
+            let message = "lockcheck found 0 lock invocations for configured targets; check your config";
+            if self.emits_diagnostics() {
+                let mut diagnostic = self.session.struct_warn(message);
+                diagnostic.code(DiagnosticId::Error("W-NO-INVOCATIONS".to_owned()));
+                diagnostic.note("lock methods are matched by the paths given in `lockcheck.toml` or `[package.metadata.lockcheck]`");
+                diagnostic.emit();
+            }
+
+            self.record_finding(Level::Warning, "W-NO-INVOCATIONS", message, None, DUMMY_SP, None);
+        }
+
+        let warnings = self.blocking_call_warnings.borrow();
+        let mut warnings = warnings.values().collect::<Vec<_>>();
+        warnings.sort_by_cached_key(|warning| self.source_location(warning.call_span));
+
+        for warning in warnings {
+            let mut multi_span = MultiSpan::from_span(warning.call_span);
+            multi_span.push_span_label(warning.invocation.callsite, format!("lock class `{}` locked here", warning.invocation.name));
+            let (message, summary) = match &warning.function {
+                Some(function) => {
+                    multi_span.push_span_label(warning.call_span, format!("`{}` may block while the lock is held", function));
+                    ("lock held across blocking call", format!("`{}` held across `{}`", warning.invocation.name, function))
+                },
+                None => {
+                    multi_span.push_span_label(warning.call_span, "the lock is held while the task is suspended here");
+                    ("lock held across await point", format!("`{}` held across await", warning.invocation.name))
+                },
+            };
+
+            if self.emits_diagnostics() {
+                let mut diagnostic = self.session.struct_span_warn(multi_span, message);
+                diagnostic.code(DiagnosticId::Error(warning.code().to_owned()));
+                diagnostic.emit();
+            }
+
+            self.record_finding(Level::Warning, warning.code(), message, Some(summary), warning.call_span, None);
+        }
+
+        let hints = self.perf_hints.borrow();
+        let mut hints = hints.values().collect::<Vec<_>>();
+        hints.sort_by_cached_key(|hint| self.source_location(hint.invocation.callsite));
+
+        for hint in hints {
+            let message = "lock acquired on every iteration of a loop";
+            let summary = format!("`{}` locked in a loop", hint.invocation.name);
+            if self.emits_diagnostics() {
+                let mut diagnostic = self.session.struct_note_without_error(message);
+                diagnostic.set_span(hint.invocation.callsite);
+                diagnostic.span_label(hint.invocation.callsite, format!("lock class `{}` is locked each time the loop runs", hint.invocation.name));
+                diagnostic.code(DiagnosticId::Error(PerfHint::CODE.to_owned()));
+                diagnostic.help("if the lock doesn't need to be released between iterations, consider locking it once before the loop");
+                diagnostic.emit();
+            }
+
+            self.record_finding(Level::Note, PerfHint::CODE, message, Some(summary), hint.invocation.callsite, None);
+        }
+
+        // findings are grouped by file, then ordered by line in each file
+        let errors = self.errors.borrow();
+        let mut errors = errors.iter().collect::<Vec<_>>();
+        errors.sort_by_cached_key(|error| self.source_location(self.primary_span(error)));
+
+        for error in errors {
+            let primary_span = self.primary_span(error);
+            let deadlock = self.deadlock_summary(error);
+            let (parent_span, child_span) = error.display_spans();
+            let mut multi_span = MultiSpan::from_span(primary_span);
+            let message = match error.kind {
+                ConflictKind::Deadlock => {
+                    multi_span.push_span_label(parent_span, format!("lock class `{}` first locked here", error.parent_invocation.name));
+                    multi_span.push_span_label(child_span, format!("deadlock occurs when lock class `{}` locked here", error.child_invocation.name));
+                    "potential deadlock detected"
+                },
+                ConflictKind::Panic => {
+                    multi_span.push_span_label(parent_span, format!("`{}` first borrowed here", error.parent_invocation.name));
+                    multi_span.push_span_label(child_span, format!("panics when `{}` borrowed here", error.child_invocation.name));
+                    "potential borrow conflict detected"
+                },
+                ConflictKind::ForbiddenOrder => {
+                    multi_span.push_span_label(parent_span, format!("lock class `{}` locked here", error.parent_invocation.name));
+                    multi_span.push_span_label(child_span, format!("lock class `{}` locked while `{}` is held", error.child_invocation.name, error.parent_invocation.name));
+                    "forbidden lock order"
+                },
+                ConflictKind::RankViolation => {
+                    multi_span.push_span_label(parent_span, format!("lock class `{}` locked here", error.parent_invocation.name));
+                    multi_span.push_span_label(child_span, format!("lock class `{}` with a lower rank locked while `{}` is held", error.child_invocation.name, error.parent_invocation.name));
+                    "lock acquired out of rank order"
+                },
+            };
         
-            self.session.struct_span_err(multi_span, "potential deadlock detected").emit();
+            let is_error = match (error.severity, self.fail_on) {
+                (Some(severity), _) => severity == Severity::Error,
+                (None, FailOn::Any) => true,
+                (None, FailOn::Guaranteed) => error.is_guaranteed(),
+            };
+
+            let level = if is_error {
+                status = ErrorStatus::DeadlockDetected;
+                Level::Error
+            } else {
+                Level::Warning
+            };
+
+            if self.emits_diagnostics() && is_error {
+                let mut diagnostic = self.session.struct_span_err(multi_span, message);
+                diagnostic.code(DiagnosticId::Error(error.code().to_owned()));
+                add_notes(&mut diagnostic, error);
+                diagnostic.emit();
+            } else if self.emits_diagnostics() {
+                let mut diagnostic = self.session.struct_span_warn(multi_span, message);
+                diagnostic.code(DiagnosticId::Error(error.code().to_owned()));
+                add_notes(&mut diagnostic, error);
+                diagnostic.emit();
+            }
+
+            let summary = format!("`{}` before `{}`", error.parent_invocation.name, error.child_invocation.name);
+            self.record_finding(level, error.code(), message, Some(summary), primary_span, Some(deadlock));
+        }
+
+        self.emit_suppressed_summary();
+        self.emit_skipped_bodies();
+        self.emit_untracked_guards();
+
+        status
+    }
+
+    /// Returns the span of the lock call which the main span of the error's diagnostic points to
+    fn primary_span(&self, error: &DeadlockError) -> Span {
+        match self.primary {
+            PrimarySpan::First => error.display_spans().0,
+            PrimarySpan::Second => error.display_spans().1,
+        }
+    }
+
+    /// Prints how many findings were suppressed by each mechanism, so suppressions don't silently hide new deadlocks
+    fn emit_suppressed_summary(&self) {
+        // a suppressed deadlock is not counted if the same deadlock was also found and reported
+        let errors = self.errors.borrow();
+        for (_, mechanism) in self.suppressed_errors.borrow().iter().filter(|(error, _)| !errors.contains(*error)) {
+            self.record_suppressed(*mechanism);
+        }
+
+        let suppressed = self.suppressed.borrow();
+        let total = suppressed.values().sum::<usize>();
+        if total == 0 || self.report {
+            return;
+        }
+
+        let mechanisms = suppressed.iter()
+            .map(|(mechanism, count)| format!("{} by {}", count, mechanism))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let message = format!(
+            "{} finding{} suppressed ({})",
+            total,
+            if total == 1 { "" } else { "s" },
+            mechanisms,
+        );
+
+        if self.format == OutputFormat::Short {
+            eprintln!("note: {}", message);
+        } else {
+            self.session.struct_note_without_error(message).emit();
+        }
+    }
+
+    /// Lists the bodies which were not analysed, so it is clear which code lockcheck could not see into
+    fn emit_skipped_bodies(&self) {
+        let mut skipped = self.skipped_bodies.borrow_mut();
+        if skipped.is_empty() || self.report {
+            return;
         }
 
-        if self.errors.borrow().len() > 0 {
-            ErrorStatus::DeadlockDetected
+        skipped.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        if self.format == OutputFormat::Short {
+            for (path, reason) in skipped.iter() {
+                eprintln!("note: `{}` not analysed ({})", path, reason.description());
+            }
         } else {
-            ErrorStatus::Ok
+            let message = format!(
+                "{} bod{} not analysed because {} mir could not be obtained",
+                skipped.len(),
+                if skipped.len() == 1 { "y was" } else { "ies were" },
+                if skipped.len() == 1 { "its" } else { "their" },
+            );
+
+            let mut diagnostic = self.session.struct_note_without_error(message);
+            for (path, reason) in skipped.iter() {
+                diagnostic.note(format!("`{}`: {}", path, reason.description()));
+            }
+            diagnostic.emit();
+        }
+    }
+
+    /// Notes where lock guards stopped being followed, since locks acquired after that point are not checked against them
+    fn emit_untracked_guards(&self) {
+        if self.report {
+            return;
         }
+
+        for (span, reason) in self.untracked_guards.borrow().iter() {
+            let message = format!("{}, locks acquired after this are not checked against it", reason);
+            if self.format == OutputFormat::Short {
+                eprintln!("{}: note: {}", self.session.source_map().span_to_diagnostic_string(*span), message);
+            } else {
+                self.session.struct_span_note_without_error(*span, message).emit();
+            }
+        }
+    }
+
+    /// Returns true if findings are emitted as rustc diagnostics,
+    /// instead of printed on one line each or only recorded for a report
+    fn emits_diagnostics(&self) -> bool {
+        !self.report && self.format == OutputFormat::Human
+    }
+
+    /// Returns every diagnostic emitted by `emit_all_errors`
+    pub fn take_findings(&self) -> Vec<Finding> {
+        self.findings.take()
+    }
+
+    /// Records a finding for reports and the cache, and prints it if the short format is used
+    fn record_finding(&self, level: Level, code: &str, message: &str, summary: Option<String>, span: Span, deadlock: Option<Deadlock>) {
+        let source = (span != DUMMY_SP).then(|| self.source_location_of(span));
+        let location = match &source {
+            Some(source) => format!("{}:{}:{}", source.file, source.line, source.column),
+            None => self.session.source_map().span_to_diagnostic_string(span),
+        };
+
+        let finding = Finding {
+            level,
+            code: code.to_owned(),
+            message: message.to_owned(),
+            summary,
+            location,
+            source,
+            deadlock,
+        };
+
+        if !self.report && self.format == OutputFormat::Short {
+            eprintln!("{}", report::short_line(&finding));
+        }
+
+        self.findings.borrow_mut().push(finding);
+    }
+
+    /// Converts a deadlock error into a form which doesn't borrow from the compiler session
+    fn deadlock_summary(&self, error: &DeadlockError) -> Deadlock {
+        let (parent_span, child_span) = error.display_spans();
+        Deadlock {
+            held: self.finding_lock(&error.parent_invocation, parent_span),
+            locked: self.finding_lock(&error.child_invocation, child_span),
+            confidence: error.confidence,
+        }
+    }
+
+    fn finding_lock(&self, invocation: &InvocationErrorInfo, span: Span) -> FindingLock {
+        FindingLock {
+            name: invocation.name.clone(),
+            ty: with_no_trimmed_paths!(invocation.ty.to_string()),
+            source: self.source_location_of(span),
+        }
+    }
+
+    fn source_location_of(&self, span: Span) -> SourceLocation {
+        let (file, line, col) = self.source_location(span);
+        SourceLocation {
+            file: self.relative_path(file),
+            line,
+            column: col + 1,
+        }
+    }
+
+    /// Returns true if any of the spans is in a changed file, or if all files are being checked
+    fn involves_changed_file(&self, spans: &[Span]) -> bool {
+        let Some(changed_files) = &self.changed_files else {
+            return true;
+        };
+
+        spans.iter()
+            .any(|span| changed_files.contains(&self.source_location_of(*span).file))
+    }
+
+    /// Makes a path relative to the workspace root, paths outside of the workspace such as dependencies are left as is
+    fn relative_path(&self, file: String) -> String {
+        let Some(workspace_root) = &self.workspace_root else {
+            return file;
+        };
+
+        // rustc paths which are not absolute are relative to the current directory
+        let Ok(path) = std::env::current_dir().map(|current_dir| current_dir.join(&file)) else {
+            return file;
+        };
+
+        match path.strip_prefix(workspace_root) {
+            // use `/` on every platform so reports are identical
+            Ok(relative_path) => relative_path.to_string_lossy().replace('\\', "/"),
+            Err(_) => file,
+        }
+    }
+
+    /// Returns the file name, line and column of the start of the span, used to sort diagnostics
+    fn source_location(&self, span: Span) -> (String, usize, usize) {
+        let location = self.session.source_map().lookup_char_pos(span.lo());
+        (location.file.name.prefer_local().to_string(), location.line, location.col.0)
+    }
+}
+
+fn add_notes(diagnostic: &mut Diagnostic, error: &DeadlockError) {
+    for note in error.notes.iter() {
+        if note.labels.is_empty() {
+            diagnostic.note(note.message.clone());
+        } else {
+            let mut note_span = MultiSpan::from_spans(note.labels.iter().map(|(span, _)| *span).collect());
+            for (span, label) in note.labels.iter() {
+                note_span.push_span_label(*span, label.clone());
+            }
+
+            diagnostic.span_note(note_span, note.message.clone());
+        }
+    }
+
+    if !error.is_guaranteed() {
+        diagnostic.note(format!(
+            "this deadlock depends on branch conditions or unanalysed function calls (confidence {}%)",
+            error.confidence,
+        ));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    /// Informational findings such as performance hints, these never fail the check
+    Note,
+}
+
+/// A summary of an emitted diagnostic, which can be stored and printed again without rerunning analysis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    pub level: Level,
+    /// Diagnostic code such as `E-DEADLOCK-SELF`
+    pub code: String,
+    pub message: String,
+    /// Short description of the locks involved, such as `` `a` before `b` ``
+    #[serde(default)]
+    pub summary: Option<String>,
+    pub location: String,
+    /// Where the finding is, this is not set for findings about the whole crate
+    #[serde(default)]
+    pub source: Option<SourceLocation>,
+    /// The locks involved, if the finding is a deadlock
+    #[serde(default)]
+    pub deadlock: Option<Deadlock>,
+}
+
+/// A position in a source file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    /// Column of the position, starting from 1
+    pub column: usize,
+}
+
+/// A deadlock which doesn't depend on the compiler session, so it can be used after analysis finishes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deadlock {
+    /// The lock which is held first
+    pub held: FindingLock,
+    /// The lock which is locked while `held` is held
+    pub locked: FindingLock,
+    /// How likely the deadlock is to occur, as a percentage
+    pub confidence: u8,
+}
+
+/// A lock call which is part of a deadlock
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindingLock {
+    /// Name of the lock class shown in diagnostics
+    pub name: String,
+    /// Full path of the lock type, such as `std::sync::Mutex<my_crate::Data>`
+    pub ty: String,
+    pub source: SourceLocation,
+}
+
+/// Returns the spans shown for two lock calls in the same diagnostic, given the span and callsite of each
+///
+/// These are the macro invocations which generated the lock calls, unless both lock calls are in the same macro invocation,
+/// then the lock calls inside the macro are shown so they aren't labelled at the same span
+pub fn display_spans((span, callsite): (Span, Span), (other_span, other_callsite): (Span, Span)) -> (Span, Span) {
+    if callsite == other_callsite && span != other_span {
+        (span, other_span)
+    } else {
+        (callsite, other_callsite)
     }
 }
 
 pub struct InvocationErrorInfo<'tcx> {
+    /// Span of the lock call, which is used to report each deadlock once
     pub span: Span,
-    pub ty: Ty<'tcx>
+    /// Span shown in diagnostics, this is the macro invocation in the user's code if the lock call was generated by a macro
+    pub callsite: Span,
+    /// Function the invocation is in
+    pub def_id: DefId,
+    pub ty: Ty<'tcx>,
+    /// Name of the lock class shown in diagnostics
+    pub name: String,
+}
+
+/// Extra information attached to a deadlock error
+pub struct ErrorNote {
+    pub message: String,
+    /// Spans which the note points to, along with their labels
+    pub labels: Vec<(Span, String)>,
+}
+
+impl ErrorNote {
+    pub fn new(message: String) -> Self {
+        ErrorNote {
+            message,
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// A lock is held while a configured blocking function is called, or across an await point
+pub struct BlockingCallWarning<'tcx> {
+    pub invocation: InvocationErrorInfo<'tcx>,
+    pub call_span: Span,
+    /// Path of the blocking function, or `None` for an await point
+    pub function: Option<String>,
+}
+
+impl BlockingCallWarning<'_> {
+    /// The diagnostic code shown with this warning
+    pub fn code(&self) -> &'static str {
+        match self.function {
+            Some(_) => "W-LOCK-ACROSS-BLOCKING",
+            None => "W-LOCK-ACROSS-AWAIT",
+        }
+    }
+}
+
+/// A lock is acquired on every iteration of a loop
+pub struct PerfHint<'tcx> {
+    pub invocation: InvocationErrorInfo<'tcx>,
+}
+
+impl PerfHint<'_> {
+    /// The diagnostic code shown with this hint
+    pub const CODE: &'static str = "H-LOCK-IN-LOOP";
+}
+
+/// What happens when a lock is acquired while a conflicting lock is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConflictKind {
+    Deadlock,
+    /// Used for locks such as `RefCell` which panic instead of blocking
+    Panic,
+    /// The locks are configured to never be nested in this order
+    ForbiddenOrder,
+    /// A lock is locked while a lock with a higher configured rank is held
+    RankViolation,
+}
+
+pub struct DeadlockError<'tcx> {
+    pub parent_invocation: InvocationErrorInfo<'tcx>,
+    pub child_invocation: InvocationErrorInfo<'tcx>,
+    pub kind: ConflictKind,
+    /// How likely the deadlock is to occur, as a percentage
+    pub confidence: u8,
+    /// Severity configured for the locks involved, which overrides `fail_on`
+    pub severity: Option<Severity>,
+    pub notes: Vec<ErrorNote>,
 }
 
-struct DeadlockError<'tcx> {
-    parent_invocation: InvocationErrorInfo<'tcx>,
-    child_invocation: InvocationErrorInfo<'tcx>,
+impl DeadlockError<'_> {
+    /// A deadlock is guaranteed if it occurs whenever the parent lock is acquired
+    pub fn is_guaranteed(&self) -> bool {
+        self.confidence == 100
+    }
+
+    /// The diagnostic code shown with this error
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            // the same lock class is locked twice
+            ConflictKind::Deadlock if self.parent_invocation.ty == self.child_invocation.ty => "E-DEADLOCK-SELF",
+            // two lock classes are locked in an inconsistent order
+            ConflictKind::Deadlock => "E-DEADLOCK-ORDER",
+            ConflictKind::Panic => "E-BORROW-CONFLICT",
+            ConflictKind::ForbiddenOrder => "E-FORBIDDEN-ORDER",
+            ConflictKind::RankViolation => "E-LOCK-RANK",
+        }
+    }
+
+    /// Returns the spans the parent and child lock calls are shown at
+    pub fn display_spans(&self) -> (Span, Span) {
+        display_spans(
+            (self.parent_invocation.span, self.parent_invocation.callsite),
+            (self.child_invocation.span, self.child_invocation.callsite),
+        )
+    }
+
+    /// Errors with the same key are only reported once
+    /// 
+    /// This is the source location of the invocation rather than anything type specific,
+    /// so a deadlock in a generic function is reported once no matter what it is instantiated with
+    fn key(&self) -> (Span, CrateNum, DefIndex, ConflictKind) {
+        let invocation = &self.child_invocation;
+        (invocation.span, invocation.def_id.krate, invocation.def_id.index, self.kind)
+    }
 }
 
 impl PartialEq for DeadlockError<'_> {
     fn eq(&self, other: &Self) -> bool {
-        self.child_invocation.span == other.child_invocation.span
+        self.key() == other.key()
     }
 }
 
@@ -85,6 +674,6 @@ impl PartialOrd for DeadlockError<'_> {
 
 impl Ord for DeadlockError<'_> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.child_invocation.span.cmp(&other.child_invocation.span)
+        self.key().cmp(&other.key())
     }
 }
\ No newline at end of file
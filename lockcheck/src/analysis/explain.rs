@@ -0,0 +1,87 @@
+use std::fmt::Write;
+
+use rustc_data_structures::fx::FxIndexSet;
+
+use super::pass::{AnalysisPass, InvocationInfo, LockClass};
+use super::shared_graph::span_location;
+
+/// Describes the relationship between the lock classes named `parent` and `child`
+///
+/// This lists every place `child` is locked while `parent` is held, and the reverse,
+/// which is useful to understand why a pair of locks is or is not reported as a deadlock
+pub fn explain(pass: &AnalysisPass, parent: &str, child: &str) -> String {
+    let mut out = String::new();
+
+    let parent_classes = pass.lock_classes_named(parent);
+    let child_classes = pass.lock_classes_named(child);
+
+    if parent_classes.is_empty() {
+        writeln!(out, "no lock class named `{}` was found", parent).unwrap();
+    }
+    if child_classes.is_empty() {
+        writeln!(out, "no lock class named `{}` was found", child).unwrap();
+    }
+    if parent_classes.is_empty() || child_classes.is_empty() {
+        return out;
+    }
+
+    let forward = write_edges(&mut out, pass, parent, &parent_classes, child, &child_classes);
+    out.push('\n');
+    let reverse = write_edges(&mut out, pass, child, &child_classes, parent, &parent_classes);
+    out.push('\n');
+
+    let cycle_exists = if parent_classes.intersection(&child_classes).next().is_some() {
+        // the same lock class is locked while it is held
+        forward
+    } else {
+        forward && reverse
+    };
+
+    if cycle_exists {
+        writeln!(out, "`{}` and `{}` are locked in both orders, this is a potential deadlock", parent, child).unwrap();
+    } else if forward || reverse {
+        writeln!(out, "`{}` and `{}` are only locked in one order, this is not a deadlock on its own", parent, child).unwrap();
+    } else {
+        writeln!(out, "`{}` and `{}` are never held at the same time", parent, child).unwrap();
+    }
+
+    out
+}
+
+/// Writes every edge from one of `from_classes` to one of `to_classes`, returns true if any edge exists
+fn write_edges(
+    out: &mut String,
+    pass: &AnalysisPass,
+    from: &str,
+    from_classes: &FxIndexSet<LockClass>,
+    to: &str,
+    to_classes: &FxIndexSet<LockClass>,
+) -> bool {
+    let mut edges = pass.invocation_edges()
+        .filter(|(parent, child)| from_classes.contains(&parent.class) && to_classes.contains(&child.class))
+        .collect::<Vec<_>>();
+    edges.sort_by_key(|(parent, child)| (parent.span, child.span));
+
+    if edges.is_empty() {
+        writeln!(out, "`{}` is never locked while `{}` is held", to, from).unwrap();
+        return false;
+    }
+
+    writeln!(out, "`{}` is locked while `{}` is held:", to, from).unwrap();
+    for (parent, child) in edges {
+        writeln!(
+            out,
+            "  {} locked at {}, then {} locked at {}",
+            class_name(pass, &parent),
+            span_location(pass.tcx(), parent.callsite),
+            class_name(pass, &child),
+            span_location(pass.tcx(), child.callsite),
+        ).unwrap();
+    }
+
+    true
+}
+
+fn class_name(pass: &AnalysisPass, invocation: &InvocationInfo) -> String {
+    format!("`{}`", pass.lock_class_name(invocation.class))
+}
\ No newline at end of file
@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use rustc_middle::ty::TyCtxt;
+use rustc_span::FileName;
+
+/// Files which the result of analysing a crate depends on
+#[derive(Debug, Clone, Default)]
+pub struct CrateInputs {
+    /// Every source file rustc loaded for the crate, including `#[path]` modules and `include!`d files
+    pub sources: Vec<PathBuf>,
+    /// Metadata of every dependency, which has the mir of generic and inlined functions
+    pub dependencies: Vec<PathBuf>,
+}
+
+impl CrateInputs {
+    pub fn from_tcx(tcx: TyCtxt) -> Self {
+        let sources = tcx.sess.source_map().files().iter()
+            // files imported from dependencies change along with their metadata
+            .filter(|source_file| !source_file.is_imported())
+            .filter_map(|source_file| match &source_file.name {
+                FileName::Real(name) => name.local_path().map(PathBuf::from),
+                _ => None,
+            })
+            .collect();
+
+        let dependencies = tcx.crates(()).iter()
+            .flat_map(|cnum| tcx.used_crate_source(*cnum).paths().cloned())
+            .collect();
+
+        CrateInputs {
+            sources,
+            dependencies,
+        }
+    }
+}
\ No newline at end of file
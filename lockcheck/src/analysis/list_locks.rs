@@ -0,0 +1,38 @@
+use std::fmt::Write;
+
+use super::pass::AnalysisPass;
+use super::shared_graph::span_location;
+
+/// Lists every place a lock is acquired, sorted by file, line and column
+///
+/// Each line has the location of the lock invocation, followed by its lock class and lock type
+pub fn list_locks(pass: &AnalysisPass) -> String {
+    let mut out = String::new();
+    let source_map = pass.tcx().sess.source_map();
+
+    let mut invocations = pass.invocations()
+        .map(|invocation| {
+            let location = source_map.lookup_char_pos(invocation.span.lo());
+            ((location.file.name.prefer_local().to_string(), location.line, location.col.0), invocation)
+        })
+        .collect::<Vec<_>>();
+    invocations.sort_by(|(location_a, _), (location_b, _)| location_a.cmp(location_b));
+
+    if invocations.is_empty() {
+        writeln!(out, "no lock invocations were found").unwrap();
+        return out;
+    }
+
+    writeln!(out, "{} lock invocations:", invocations.len()).unwrap();
+    for (_, invocation) in invocations {
+        writeln!(
+            out,
+            "  {}: `{}` ({})",
+            span_location(pass.tcx(), invocation.callsite),
+            pass.lock_class_name(invocation.class),
+            invocation.ty,
+        ).unwrap();
+    }
+
+    out
+}
\ No newline at end of file
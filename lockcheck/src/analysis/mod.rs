@@ -1,19 +1,38 @@
+mod entry_points;
 mod errors;
+mod explain;
+mod inputs;
+mod list_locks;
 mod pass;
+mod progress;
+mod shared_graph;
+mod stats;
 
+use std::rc::Rc;
 use std::str;
 use std::fmt::Write;
 use std::ops::BitOr;
 
 use rustc_span::{symbol::Symbol, def_id::DefId};
-use rustc_hir::{ItemKind, Node, ExprKind, StmtKind, Ty, TyKind, Expr};
-use rustc_middle::ty::{TypeckResults, TyCtxt};
-use anyhow::Result;
+use rustc_hir::{ItemKind, Node, ExprKind, Stmt, StmtKind, Ty, TyKind, Expr};
+use rustc_middle::ty::{self, TypeckResults, TyCtxt};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use anyhow::{Result, bail};
 
-use crate::config::Config as LockCheckConfig;
+use crate::args::Args;
+use crate::config::{Config as LockCheckConfig, LockCheckTarget, LockReturn};
+use crate::report;
 use crate::rustc_config::get_rustc_config;
-use pass::{AnalysisPass, AnalysisPassTarget};
-use errors::{Errors, ErrorStatus};
+use pass::AnalysisPassTarget;
+use errors::Errors;
+
+pub use pass::{AnalysisPass, InvocationInfo, LockClass, PassOptions};
+pub use errors::{ErrorStatus, Finding, Level, Deadlock, FindingLock, SourceLocation};
+pub use explain::explain;
+pub use inputs::CrateInputs;
+pub use list_locks::list_locks;
+pub use stats::Stats;
+pub use shared_graph::{SharedGraph, CrateGraph};
 
 impl BitOr for ErrorStatus {
     type Output = Self;
@@ -26,14 +45,38 @@ impl BitOr for ErrorStatus {
     }
 }
 
-#[derive(Default)]
 struct AnalysisCtx<'tcx> {
-    passes: Vec<AnalysisPass<'tcx>>,
+    pass: AnalysisPass<'tcx>,
 }
 
 impl<'tcx> AnalysisCtx<'tcx> {
-    fn parse_passes_from_hir(tcx: TyCtxt<'tcx>) -> Self {
-        let mut passes = Vec::new();
+    fn parse_pass_from_hir(tcx: TyCtxt<'tcx>, config: &LockCheckConfig, options: PassOptions) -> Self {
+        let mut targets = Vec::new();
+
+        for (index, (target, lock_config)) in Self::resolve_targets(tcx, config).into_iter().zip(config.locks.iter()).enumerate() {
+            match target {
+                Some(target) => targets.push(target),
+                // skip the target so the other targets are still checked
+                None => {
+                    let mut diagnostic = tcx.sess.struct_warn(format!(
+                        "could not set up checking for lock target {} (`{}`)",
+                        index + 1,
+                        lock_config.lock,
+                    ));
+                    diagnostic.note("check that the lock, constructor, lock method and guard paths in the config are correct");
+                    diagnostic.emit();
+                },
+            }
+        }
+
+        AnalysisCtx {
+            pass: AnalysisPass::new(targets, Rc::new(config.clone()), options, tcx),
+        }
+    }
+
+    /// Resolves each lock target in the config from the lock filler, targets which could not be resolved are `None`
+    fn resolve_targets(tcx: TyCtxt<'tcx>, config: &LockCheckConfig) -> Vec<Option<AnalysisPassTarget>> {
+        let mut targets = config.locks.iter().map(|_| None).collect::<Vec<_>>();
 
         let hir = tcx.hir();
 
@@ -60,65 +103,108 @@ impl<'tcx> AnalysisCtx<'tcx> {
                 };
     
                 // each lock rule will generate 3 statements
-                for statements in block.stmts.chunks_exact(3) {
-                    let StmtKind::Local(lock_new) = statements[0].kind else {
-                        invalid_hir();
-                    };
-    
-                    let StmtKind::Local(lock_method) = statements[1].kind else {
-                        invalid_hir();
-                    };
+                for (target, (statements, lock_config)) in targets.iter_mut().zip(block.stmts.chunks_exact(3).zip(config.locks.iter())) {
+                    *target = Self::parse_target(statements, lock_config, typecheck);
+                }
+            }
+        }
 
-                    let StmtKind::Local(lock_guard) = statements[2].kind else {
-                        invalid_hir();
-                    };
+        targets
+    }
 
-                    let lock_def_id = Self::parse_def_id_from_ty(lock_new.ty.unwrap(), &typecheck);
-                    let guard_def_id = Self::parse_def_id_from_ty(&lock_guard.ty.unwrap(), &typecheck);
+    /// Resolves the paths in the 3 statements generated for a lock, returns `None` if they don't have the expected form
+    fn parse_target(statements: &[Stmt], lock_config: &LockCheckTarget, typecheck: &TypeckResults) -> Option<AnalysisPassTarget> {
+        let StmtKind::Local(lock_new) = statements[0].kind else {
+            return None;
+        };
 
-                    let lock_constructor_def_id = Self::parse_def_id_from_call_expr(lock_new.init.unwrap(), &typecheck);
-                    let lock_method_def_id = Self::parse_def_id_from_call_expr(lock_method.init.unwrap(), &typecheck);
+        let StmtKind::Local(lock_method) = statements[1].kind else {
+            return None;
+        };
 
-                    let pass = AnalysisPass::new(AnalysisPassTarget {
-                        lock: lock_def_id,
-                        lock_constructor: lock_constructor_def_id,
-                        lock_method: lock_method_def_id,
-                        guard: guard_def_id,
-                    }, tcx);
-                    passes.push(pass);
-                }
-            }
-        }
+        let StmtKind::Local(lock_guard) = statements[2].kind else {
+            return None;
+        };
 
-        AnalysisCtx {
-            passes,
-        }
+        // types are taken from the typechecked bindings rather than the written paths,
+        // so type aliases such as `parking_lot::Mutex<T>` resolve to the type they stand for
+        let lock_ty = typecheck.node_type_opt(lock_new.pat.hir_id)?;
+        let ty::Adt(lock_adt_def, lock_generic_args) = lock_ty.kind() else {
+            return None;
+        };
+
+        // an alias can hide some of the lock's type parameters (such as the `RawMutex` of `lock_api::Mutex<RawMutex, T>`),
+        // so the guarded value is found by where the `u8` guarded in the filler ended up
+        let value_index = if Self::parse_def_id_from_ty(lock_new.ty?, typecheck)? == lock_adt_def.did() {
+            lock_config.value_index
+        } else {
+            lock_generic_args.types().position(|ty| matches!(ty.kind(), ty::Uint(ty::UintTy::U8)))?
+        };
+        // a `value_index` past the lock's type parameters can't identify lock classes
+        lock_generic_args.types().nth(value_index)?;
+
+        // the guard type is not annotated if the lock has no guard, and is a tuple if the lock has multiple guards
+        let guard_def_ids = match lock_guard.ty {
+            Some(_) => {
+                let guard_ty = typecheck.node_type_opt(lock_guard.pat.hir_id)?;
+                let guard_tys = match guard_ty.kind() {
+                    ty::Tuple(tys) => tys.iter().collect(),
+                    _ => vec![guard_ty],
+                };
+
+                guard_tys.iter()
+                    .map(|guard_ty| guard_ty.ty_adt_def().map(|adt_def| adt_def.did()))
+                    .collect::<Option<Vec<_>>>()?
+            },
+            None => Vec::new(),
+        };
+
+        let lock_constructor_def_id = Self::parse_def_id_from_call_expr(lock_new.init?, typecheck)?;
+        let lock_method_def_id = Self::parse_def_id_from_call_expr(lock_method.init?, typecheck)?;
+
+        Some(AnalysisPassTarget {
+            lock: lock_adt_def.did(),
+            lock_constructor: lock_constructor_def_id,
+            lock_method: lock_method_def_id,
+            guards: guard_def_ids,
+            name: lock_config.name.clone(),
+            by_value: lock_config.by_value,
+            held_during_closure: lock_config.held_during_closure,
+            held_during_init: lock_config.held_during_init,
+            shared: lock_config.shared,
+            panics: lock_config.panics,
+            is_async: lock_config.returns == LockReturn::Future,
+            trait_method: lock_config.trait_method,
+            value_index,
+            severity: lock_config.severity,
+            rank: lock_config.rank,
+            blocking: lock_config.blocking,
+        })
     }
 
-    fn parse_def_id_from_ty(ty: &Ty, typecheck: &TypeckResults) -> DefId {
+    fn parse_def_id_from_ty(ty: &Ty, typecheck: &TypeckResults) -> Option<DefId> {
         let TyKind::Path(ref ty_path) = ty.kind else {
-            invalid_hir();
+            return None;
         };
 
-        typecheck.qpath_res(ty_path, ty.hir_id).def_id()
+        // paths which failed to resolve have no def id
+        typecheck.qpath_res(ty_path, ty.hir_id).opt_def_id()
     }
 
-    fn parse_def_id_from_call_expr(expr: &Expr, typecheck: &TypeckResults) -> DefId {
+    fn parse_def_id_from_call_expr(expr: &Expr, typecheck: &TypeckResults) -> Option<DefId> {
         let ExprKind::Call(call_expr, _) = expr.kind else {
-            invalid_hir();
+            return None;
         };
 
         let ExprKind::Path(ref ty_path) = call_expr.kind else {
-            invalid_hir();
+            return None;
         };
 
-        typecheck.qpath_res(ty_path, call_expr.hir_id).def_id()
+        typecheck.qpath_res(ty_path, call_expr.hir_id).opt_def_id()
     }
 
-    fn run_passes(&mut self, errors: &mut Errors<'tcx>) {
-        for pass in self.passes.iter_mut() {
-            pass.run_pass(errors);
-        }
+    fn run_pass(&mut self, errors: &mut Errors<'tcx>, shared_graph: &SharedGraph) {
+        self.pass.run_pass(errors, shared_graph);
     }
 }
 
@@ -132,48 +218,173 @@ const LOCK_FILLER_FN_NAME: &'static str = "__lock_check_resolve";
 /// 
 /// This is a hack to get around the fact that I have no idea how to resolve
 /// a type name to a DefId except by the lowering process from ast to hir
+///
+/// The generated code must not use anything from `std`, so it also compiles in `#![no_std]` crates
 pub fn generate_lock_filler(config: &LockCheckConfig) -> Result<String> {
     let mut body = String::new();
     for lock in config.locks.iter() {
+        let lock_arg = if lock.by_value {
+            "lock"
+        } else {
+            "&lock"
+        };
+
+        let closure_arg = if lock.held_during_closure {
+            ", || 0"
+        } else {
+            ""
+        };
+
+        // type parameters before the guarded value are left for the compiler to infer
+        let generic_args = format!("{}u8", "_, ".repeat(lock.value_index));
+
+        let guard_expr = match lock.returns {
+            LockReturn::Guard => "guard_result",
+            // matched instead of unwrapped, so the error type of methods such as `try_lock` doesn't need to implement `Debug`
+            LockReturn::Result => "match guard_result { Ok(guard) => guard, Err(_) => loop {} }",
+            LockReturn::Option => "match guard_result { Some(guard) => guard, None => loop {} }",
+            // the future can't be awaited here, so the guard type is only used to annotate a diverging expression
+            LockReturn::Future => "{ let _ = guard_result; loop {} }",
+        };
+
+        // locks without a guard still get a statement so every lock generates the same number of statements
+        let guard_statement = match lock.guard.as_ref().map(|guards| guards.paths()) {
+            Some([]) => bail!("`guard` of lock `{}` is an empty list", lock.lock),
+            Some([guard]) => format!("let _guard: {}<{}> = {};", guard, generic_args, guard_expr),
+            // only the first guard is returned by the lock method, the other guards annotate diverging expressions
+            Some([guard, other_guards @ ..]) => format!(
+                "let _guard: ({}<{}>, {}) = ({}, {});",
+                guard,
+                generic_args,
+                other_guards.iter().map(|other_guard| format!("{}<{}>", other_guard, generic_args)).collect::<Vec<_>>().join(", "),
+                guard_expr,
+                vec!["loop {}"; other_guards.len()].join(", "),
+            ),
+            None => "let _guard = guard_result;".to_owned(),
+        };
+
         write!(
             body,
             r#"
-                let lock: {}<u8> = {}(0);
-                // TODO: get rid of unwrap
-                let guard_result = {}(&lock);
-                let _guard: {}<u8> = guard_result.unwrap();
+                let lock: {}<{}> = {}({});
+                let guard_result = {}({}{});
+                {}
             "#,
             lock.lock,
+            generic_args,
             lock.constructor,
+            lock.constructor_args,
             lock.lock_method,
-            lock.guard,
+            lock_arg,
+            closure_arg,
+            guard_statement,
         )?;
     }
 
     Ok(format!(r#"
-    #[allow(dead_code)]
+    #[allow(dead_code, unreachable_code)]
     fn {}() {{
         {}
     }}"#, LOCK_FILLER_FN_NAME, body))
 }
 
-pub fn run(config: &LockCheckConfig) -> Result<ErrorStatus> {
-    let rustc_config = get_rustc_config(&config)?;
+/// Resolves every lock target in the config and prints whether it resolved, without running analysis
+///
+/// Returns false if any target could not be resolved
+pub fn check_config(config: &LockCheckConfig, args: &Args) -> Result<bool> {
+    let rustc_config = get_rustc_config(&config, args)?;
 
-    let status = rustc_interface::run_compiler(rustc_config, |compiler| {
+    rustc_interface::run_compiler(rustc_config, |compiler| {
+        compiler.enter(|queries| {
+            queries.global_ctxt().unwrap().enter(|tcx| {
+                let targets = AnalysisCtx::resolve_targets(tcx, config);
+                let path = |def_id: DefId| with_no_trimmed_paths!(tcx.def_path_str(def_id));
+
+                for (index, (target, lock_config)) in targets.iter().zip(config.locks.iter()).enumerate() {
+                    let Some(target) = target else {
+                        println!("target {} (`{}`): could not be resolved", index + 1, lock_config.lock);
+                        continue;
+                    };
+
+                    println!("target {} (`{}`): ok", index + 1, lock_config.lock);
+                    println!("    lock: {}", path(target.lock));
+                    for guard in target.guards.iter() {
+                        println!("    guard: {}", path(*guard));
+                    }
+                    println!("    constructor: {}", path(target.lock_constructor));
+                    println!("    lock method: {}", path(target.lock_method));
+                }
+
+                Ok(targets.iter().all(Option::is_some))
+            })
+        })
+    })
+}
+
+pub fn run(config: &LockCheckConfig, args: &Args) -> Result<ErrorStatus> {
+    run_with_pass(config, args, |_| ()).map(|(status, _)| status)
+}
+
+/// Runs analysis, and calls `f` with the completed analysis pass before errors are emitted
+///
+/// This allows library users to query the lock dependancy graph computed by the pass
+pub fn run_with_pass<T: Send>(
+    config: &LockCheckConfig,
+    args: &Args,
+    f: impl for<'tcx> FnOnce(&AnalysisPass<'tcx>) -> T + Send,
+) -> Result<(ErrorStatus, T)> {
+    run_inner(config, args, f).map(|(status, _, _, _, value)| (status, value))
+}
+
+/// Runs analysis, and also returns a summary of every diagnostic which was emitted, the crate's lock dependancy edges,
+/// and the files the result depends on
+pub fn run_with_findings(config: &LockCheckConfig, args: &Args) -> Result<(ErrorStatus, Vec<Finding>, CrateGraph, CrateInputs)> {
+    run_inner(config, args, |_| ()).map(|(status, findings, crate_graph, inputs, _)| (status, findings, crate_graph, inputs))
+}
+
+fn run_inner<T: Send>(
+    config: &LockCheckConfig,
+    args: &Args,
+    f: impl for<'tcx> FnOnce(&AnalysisPass<'tcx>) -> T + Send,
+) -> Result<(ErrorStatus, Vec<Finding>, CrateGraph, CrateInputs, T)> {
+    let rustc_config = get_rustc_config(&config, args)?;
+
+    rustc_interface::run_compiler(rustc_config, |compiler| {
         compiler.enter(|queries| {
             let _crate_ast = queries.parse().unwrap().get_mut().clone();
 
             queries.global_ctxt().unwrap().enter(|tcx| {
-                let mut analysis_ctx = AnalysisCtx::parse_passes_from_hir(tcx,);
-                let mut errors = Errors::new(compiler.session().clone());
+                let crate_key = shared_graph::crate_key(tcx);
+                let shared_graph = SharedGraph::load(args.shared_graph_dir.as_deref(), &crate_key)?;
+
+                let options = PassOptions {
+                    pessimistic: args.pessimistic,
+                    progress: args.progress,
+                    perf_hints: args.perf_hints,
+                    entry_points: args.entry_points,
+                    skipped_bodies: args.skipped_bodies,
+                    untracked_guards: args.untracked_guards,
+                };
+                let mut analysis_ctx = AnalysisCtx::parse_pass_from_hir(tcx, config, options);
+                let mut errors = Errors::new(compiler.session().clone(), args.min_confidence, config.fail_on, args.format, args.writes_report(), args.primary, args.changed_files.clone());
+
+                analysis_ctx.run_pass(&mut errors, &shared_graph);
+                let crate_graph = CrateGraph {
+                    crate_key,
+                    graph: SharedGraph::from_pass(tcx, &analysis_ctx.pass),
+                };
+                crate_graph.save(args.shared_graph_dir.as_deref())?;
+
+                let value = f(&analysis_ctx.pass);
 
-                analysis_ctx.run_passes(&mut errors);
+                let status = errors.emit_all_errors();
+                let findings = errors.take_findings();
+                if args.writes_report() {
+                    report::write_report(&findings, args)?;
+                }
 
-                errors.emit_all_errors()
+                Ok((status, findings, crate_graph, CrateInputs::from_tcx(tcx), value))
             })
         })
-    });
-
-    Ok(status)
+    })
 }
\ No newline at end of file
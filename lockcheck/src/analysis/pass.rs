@@ -1,29 +1,65 @@
 use std::collections::{HashSet, HashMap};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::cell::RefCell;
+use std::rc::Rc;
 
-use rustc_span::{Span, symbol::Symbol, def_id::DefId};
-use rustc_middle::ty::{TyCtxt, TyKind, Ty};
-use rustc_middle::mir::{BasicBlock, Terminator, TerminatorKind, Operand, Const, ConstValue, Body, Local, Statement, StatementKind, Rvalue, START_BLOCK};
+use rustc_data_structures::fx::{FxIndexMap, FxIndexSet};
+use rustc_span::{Span, ExpnKind, symbol::{Symbol, sym, kw}, def_id::DefId};
+use rustc_middle::ty::{self, TyCtxt, TyKind, Ty, TypeFolder, TypeFoldable, TypeSuperFoldable, adjustment::PointerCoercion};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_middle::mir::{BasicBlock, Terminator, TerminatorKind, Operand, Const, ConstValue, Body, Local, Statement, StatementKind, Rvalue, CastKind, Place, BorrowKind, ProjectionElem, START_BLOCK};
 use rustc_middle::mir::traversal::reachable;
-use rustc_hir::ItemKind;
-
-use super::errors::{InvocationErrorInfo, Errors};
+use rustc_hir::def::DefKind;
+use rustc_hir::LangItem;
+use rustc_target::abi::FieldIdx;
+use rustc_ast::Mutability;
+
+use super::errors::{display_spans, InvocationErrorInfo, Errors, ErrorNote, DeadlockError, ConflictKind, BlockingCallWarning, PerfHint};
+use super::shared_graph::{SharedGraph, ty_name};
+use super::entry_points::EntryPoints;
+use super::progress::Progress;
 use super::LOCK_FILLER_FN_NAME;
-use crate::tyctxt_ext::TyCtxtExt;
+use crate::config::{Config, Severity, path_matches};
+use crate::tyctxt_ext::{TyCtxtExt, MissingMir};
 
 #[derive(Debug)]
 pub struct AnalysisPassTarget {
     pub lock: DefId,
     pub lock_constructor: DefId,
     pub lock_method: DefId,
-    pub guard: DefId,
+    /// Guard types of the lock, starting with the guard returned by the lock method
+    pub guards: Vec<DefId>,
+    /// Name used for lock classes of this target in diagnostics instead of the lock's path, the guarded type is still shown
+    pub name: Option<String>,
+    /// If true the lock method takes the lock by value instead of by reference
+    pub by_value: bool,
+    /// If true the lock is only held while a closure passed to the lock method runs
+    pub held_during_closure: bool,
+    /// If true the lock is only held while the init function given to the lock constructor runs
+    pub held_during_init: bool,
+    /// If true the lock method only takes shared access, which does not conflict with other shared accesses
+    pub shared: bool,
+    /// If true acquiring the lock while it is held panics instead of blocking
+    pub panics: bool,
+    /// If true the lock method returns a future which resolves to the guard
+    pub is_async: bool,
+    /// If true the lock method is a trait method, and the lock is any type the method is called on
+    pub trait_method: bool,
+    /// Index of the lock's type parameter which is the guarded value
+    pub value_index: usize,
+    /// How deadlocks involving this lock are reported, overriding `fail_on`
+    pub severity: Option<Severity>,
+    /// Position of the lock in the lock hierarchy, a lock can't be locked while a lock with a higher rank is held
+    pub rank: Option<u32>,
+    /// If false the lock method never waits for the lock, such as `try_lock`, so it can't deadlock while another lock is held
+    pub blocking: bool,
 }
 
 static NEXT_LOCK_CLASS: AtomicU64 = AtomicU64::new(0);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct LockClass(u64);
+/// Identifies a group of locks of the same lock type which guard the same type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LockClass(u64);
 
 impl LockClass {
     fn new() -> Self {
@@ -31,53 +67,197 @@ impl LockClass {
     }
 }
 
+/// Replaces the names of generic type parameters so they are identified only by their index
+///
+/// This makes `Mutex<U>` in `fn a<U>()` and `Mutex<T>` in `fn b<T>()` the same lock class
+struct ParamNormalizer<'tcx> {
+    tcx: TyCtxt<'tcx>,
+}
+
+impl<'tcx> TypeFolder<TyCtxt<'tcx>> for ParamNormalizer<'tcx> {
+    fn interner(&self) -> TyCtxt<'tcx> {
+        self.tcx
+    }
+
+    fn fold_ty(&mut self, ty: Ty<'tcx>) -> Ty<'tcx> {
+        match ty.kind() {
+            TyKind::Param(param) => Ty::new_param(self.tcx, param.index, kw::Empty),
+            _ => ty.super_fold_with(self),
+        }
+    }
+}
+
 #[derive(Default)]
 struct LockClassTyMap<'tcx> {
+    /// If true, lifetimes in the guarded type are ignored when finding its lock class
+    erase_regions: bool,
+    /// Paths of guarded types whose lock classes are ignored
+    ignore_types: Vec<String>,
+    /// Lock classes of guarded types in `ignore_types`, these never conflict with other locks
+    ignored: HashSet<LockClass>,
+    /// Maps each class to the type of the lock
     class_to_ty: HashMap<LockClass, Ty<'tcx>>,
-    ty_to_class: HashMap<Ty<'tcx>, LockClass>,
+    /// Maps each class to the type guarded by the lock
+    class_to_guarded_ty: HashMap<LockClass, Ty<'tcx>>,
+    /// Maps each class to the index of the target it was first found for
+    class_to_target: HashMap<LockClass, usize>,
+    /// Lock classes are identified by the lock type's def id and the type guarded by the lock
+    ty_to_class: HashMap<(DefId, Ty<'tcx>), LockClass>,
 }
 
 impl<'tcx> LockClassTyMap<'tcx> {
-    fn get_lock_class(&mut self, ty: Ty<'tcx>) -> LockClass {
-        if let Some(class) = self.ty_to_class.get(&ty) {
+    fn get_lock_class(&mut self, tcx: TyCtxt<'tcx>, lock_ty: Ty<'tcx>, lock: DefId, guarded_ty: Ty<'tcx>, target: usize) -> LockClass {
+        let guarded_ty = if self.erase_regions {
+            tcx.erase_regions(guarded_ty)
+        } else {
+            guarded_ty
+        };
+
+        // the first type seen is still used for diagnostics, so the user sees their own generic parameter names
+        let key = (lock, guarded_ty.fold_with(&mut ParamNormalizer { tcx }));
+
+        if let Some(class) = self.ty_to_class.get(&key) {
             *class
         } else {
             let class = LockClass::new();
-            self.class_to_ty.insert(class, ty);
-            self.ty_to_class.insert(ty, class);
+            if self.is_ignored_type(tcx, guarded_ty) {
+                self.ignored.insert(class);
+            }
+            self.class_to_ty.insert(class, lock_ty);
+            self.class_to_guarded_ty.insert(class, guarded_ty);
+            self.class_to_target.insert(class, target);
+            self.ty_to_class.insert(key, class);
             class
         }
     }
 
+    fn is_ignored_type(&self, tcx: TyCtxt<'tcx>, guarded_ty: Ty<'tcx>) -> bool {
+        let Some(adt_def) = guarded_ty.ty_adt_def() else {
+            return false;
+        };
+
+        let path = with_no_trimmed_paths!(tcx.def_path_str(adt_def.did()));
+        self.ignore_types.iter()
+            .any(|ignored_type| path_matches(ignored_type, &path))
+    }
+
+    fn is_ignored(&self, class: LockClass) -> bool {
+        self.ignored.contains(&class)
+    }
+
     fn get_ty(&self, class: LockClass) -> Ty<'tcx> {
         self.class_to_ty[&class]
     }
 
-    fn get_invocation_error_info(&self, invocation: &LockInvocation) -> InvocationErrorInfo<'tcx> {
-        InvocationErrorInfo {
-            span: invocation.span,
-            ty: self.get_ty(invocation.class),
-        }
+    fn get_guarded_ty(&self, class: LockClass) -> Ty<'tcx> {
+        self.class_to_guarded_ty[&class]
+    }
+
+    fn get_target(&self, class: LockClass) -> usize {
+        self.class_to_target[&class]
     }
 }
 
+/// Information about a lock invocation found by an analysis pass
+#[derive(Debug, Clone, Copy)]
+pub struct InvocationInfo<'tcx> {
+    pub class: LockClass,
+    /// Type of the lock
+    pub ty: Ty<'tcx>,
+    pub span: Span,
+    /// Span shown in diagnostics, this is the macro invocation in the user's code if the lock call was generated by a macro
+    pub callsite: Span,
+    /// Function the invocation is in
+    pub def_id: DefId,
+}
+
 #[derive(Debug)]
 pub struct LockInvocation {
     class: LockClass,
-    child_invocations: RefCell<HashSet<Bbid>>,
+    /// Index of the target in the analysis pass which this lock invocation is for
+    target: usize,
+    child_invocations: RefCell<FxIndexMap<Bbid, PathInfo>>,
+    /// Calls to blocking functions made while this lock is held
+    blocking_calls: RefCell<Vec<BlockingCall>>,
+    /// Function the invocation is in
+    def_id: DefId,
+    /// Span of the lock call, this is also used to report each lock call once
     span: Span,
+    /// Span shown in diagnostics, this is the macro invocation in the user's code if the lock call was generated by a macro
+    callsite: Span,
+    /// Description of the outermost macro which generated the lock call, such as `lock_all!` or `#[derive(Locked)]`
+    macro_expansion: Option<String>,
+    /// True if the lock is a temporary created in the same expression it is locked in, such as `Mutex::new(0).lock()`
+    transient: bool,
+    /// Where the lock is in the function's arguments, used to tell apart locks of the same class in different fields
+    place: Option<LockPlace>,
 }
 
 impl LockInvocation {
-    fn new(class: LockClass, span: Span) -> Self {
+    fn new(class: LockClass, target: usize, def_id: DefId, span: Span, transient: bool, place: Option<LockPlace>) -> Self {
+        let (callsite, macro_expansion) = macro_callsite(span);
+
         LockInvocation {
             class,
-            child_invocations: RefCell::new(HashSet::new()),
+            target,
+            child_invocations: RefCell::new(FxIndexMap::default()),
+            blocking_calls: RefCell::new(Vec::new()),
+            def_id,
             span,
+            callsite,
+            macro_expansion,
+            transient,
+            place,
+        }
+    }
+
+    /// Returns true if both invocations are known to lock different locks,
+    /// because they lock different fields or elements of the same argument of the function they are both in
+    fn locks_different_place(&self, other: &LockInvocation) -> bool {
+        let (Some(place), Some(other_place)) = (&self.place, &other.place) else {
+            return false;
+        };
+
+        if self.def_id != other.def_id || place.local != other_place.local {
+            return false;
         }
+
+        // if one place is a prefix of the other they could overlap
+        let Some(diverging_index) = place.projection.iter()
+            .zip(other_place.projection.iter())
+            .position(|(step, other_step)| step != other_step) else {
+            return false;
+        };
+
+        let distinct_steps = matches!(
+            (place.projection[diverging_index], other_place.projection[diverging_index]),
+            (LockPlaceStep::Field(_), LockPlaceStep::Field(_)) | (LockPlaceStep::Index(_), LockPlaceStep::Index(_))
+        );
+
+        // references in different fields can still point to the same lock, a box can't be shared
+        distinct_steps
+            && !place.projection[diverging_index..].contains(&LockPlaceStep::Deref)
+            && !other_place.projection[diverging_index..].contains(&LockPlaceStep::Deref)
     }
 }
 
+/// A lock reached from a function argument through fields and constant indexes, such as `self.locks[0]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LockPlace {
+    local: Local,
+    projection: Vec<LockPlaceStep>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockPlaceStep {
+    /// Dereference of a reference or pointer
+    Deref,
+    /// Dereference of a `Box`, which owns the value it points to
+    BoxDeref,
+    Field(usize),
+    Index(u64),
+}
+
 /// Basic Block ID
 /// 
 /// Uniquely identifies any basic block in the whole program
@@ -105,76 +285,351 @@ impl Bbid {
     }
 }
 
+/// Options which change how an analysis pass runs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PassOptions {
+    /// If set, functions which can't be analysed are assumed to keep a guard passed to them held
+    pub pessimistic: bool,
+    /// If set, the number of functions and invocations analysed so far is periodically printed to stderr
+    pub progress: bool,
+    /// If set, locks acquired on every iteration of a loop are reported as performance hints
+    pub perf_hints: bool,
+    /// If set, deadlocks note which entry points they are reachable from
+    pub entry_points: bool,
+    /// If set, a note lists every body which was skipped because its mir could not be obtained
+    pub skipped_bodies: bool,
+    /// If set, a note is emitted for every lock guard which stopped being followed because optimized mir reused its local
+    pub untracked_guards: bool,
+}
+
 pub struct AnalysisPass<'tcx> {
     tcx: TyCtxt<'tcx>,
-    pass_target: AnalysisPassTarget,
-    invocations: HashMap<Bbid, LockInvocation>,
+    targets: Vec<AnalysisPassTarget>,
+    config: Rc<Config>,
+    options: PassOptions,
+    invocations: FxIndexMap<Bbid, LockInvocation>,
+    /// Every body which was scanned for lock invocations
+    bodies: Vec<DefId>,
+    /// Bodies which were skipped because their mir could not be obtained
+    skipped_bodies: RefCell<FxIndexMap<DefId, MissingMir>>,
+    /// Spans where a lock guard stopped being followed, and why
+    untracked_guards: RefCell<FxIndexMap<Span, &'static str>>,
     return_map: FunctionReturnMap,
     lock_class_ty_map: LockClassTyMap<'tcx>,
+    dependant_map: FxIndexMap<LockClass, FxIndexSet<LockClass>>,
 }
 
 impl<'tcx> AnalysisPass<'tcx> {
-    pub fn new(pass_target: AnalysisPassTarget, tcx: TyCtxt<'tcx>) -> Self {
+    pub fn new(targets: Vec<AnalysisPassTarget>, config: Rc<Config>, options: PassOptions, tcx: TyCtxt<'tcx>) -> Self {
+        let lock_class_ty_map = LockClassTyMap {
+            erase_regions: config.erase_regions,
+            ignore_types: config.ignore_types.clone(),
+            ..LockClassTyMap::default()
+        };
+
         AnalysisPass {
             tcx,
-            pass_target,
-            invocations: HashMap::new(),
+            targets,
+            config,
+            options,
+            invocations: FxIndexMap::default(),
+            bodies: Vec::new(),
+            skipped_bodies: RefCell::default(),
+            untracked_guards: RefCell::default(),
             return_map: FunctionReturnMap::default(),
-            lock_class_ty_map: LockClassTyMap::default(),
+            lock_class_ty_map,
+            dependant_map: FxIndexMap::default(),
         }
     }
 
-    fn is_terminator_lock_invocation(&self, terminator: &Terminator) -> bool {
-        if let Some(def_id) = get_fn_def_id_from_terminator(terminator) {
-            def_id == self.pass_target.lock_method
-        } else {
-            false
+    pub fn tcx(&self) -> TyCtxt<'tcx> {
+        self.tcx
+    }
+
+    /// Returns the type of the locks in the given lock class
+    pub fn lock_class_ty(&self, class: LockClass) -> Ty<'tcx> {
+        self.lock_class_ty_map.get_ty(class)
+    }
+
+    /// Returns the name used to refer to a lock class in diagnostics
+    ///
+    /// A configured target name replaces the lock's path, but the guarded type is kept so classes of the same target differ
+    pub fn lock_class_name(&self, class: LockClass) -> String {
+        let target = &self.targets[self.lock_class_ty_map.get_target(class)];
+
+        match &target.name {
+            Some(name) => format!("{}<{}>", name, self.lock_class_ty_map.get_guarded_ty(class)),
+            None => self.lock_class_ty(class).to_string(),
         }
     }
 
-    fn lock_class_from_terminator(&mut self, mir_body: &Body<'tcx>, basic_block: BasicBlock) -> Option<LockClass> {
-        let terminator = mir_body.basic_blocks[basic_block].terminator();
+    fn get_invocation_error_info(&self, invocation: &LockInvocation) -> InvocationErrorInfo<'tcx> {
+        InvocationErrorInfo {
+            span: invocation.span,
+            callsite: invocation.callsite,
+            def_id: invocation.def_id,
+            ty: self.lock_class_ty(invocation.class),
+            name: self.lock_class_name(invocation.class),
+        }
+    }
 
-        if !self.is_terminator_lock_invocation(terminator) {
-            return None;
+    fn invocation_info(&self, invocation: &LockInvocation) -> InvocationInfo<'tcx> {
+        InvocationInfo {
+            class: invocation.class,
+            ty: self.lock_class_ty(invocation.class),
+            span: invocation.span,
+            callsite: invocation.callsite,
+            def_id: invocation.def_id,
         }
+    }
+
+    /// Returns all lock invocations found by this pass
+    /// 
+    /// This is empty until `run_pass` is called
+    pub fn invocations(&self) -> impl Iterator<Item = InvocationInfo<'tcx>> + '_ {
+        self.invocations.values().map(|invocation| self.invocation_info(invocation))
+    }
+
+    /// Returns every pair of invocations where the second invocation can occur while the first invocation's guard is held
+    pub fn invocation_edges(&self) -> impl Iterator<Item = (InvocationInfo<'tcx>, InvocationInfo<'tcx>)> + '_ {
+        self.invocations.values().flat_map(move |invocation| {
+            let children = invocation.child_invocations.borrow()
+                .keys()
+                .map(|child_id| self.invocation_info(&self.invocations[child_id]))
+                .collect::<Vec<_>>();
+
+            let parent = self.invocation_info(invocation);
+            children.into_iter().map(move |child| (parent, child))
+        })
+    }
+
+    /// Returns a map from each lock class to all lock classes which may be locked while it is held
+    /// 
+    /// This is empty until `run_pass` is called
+    pub fn dependant_map(&self) -> &FxIndexMap<LockClass, FxIndexSet<LockClass>> {
+        &self.dependant_map
+    }
+
+    /// Returns the lock classes which may be locked while a lock of `class` is held
+    pub fn dependants_of(&self, class: LockClass) -> impl Iterator<Item = LockClass> + '_ {
+        self.dependant_map.get(&class)
+            .into_iter()
+            .flat_map(|dependants| dependants.iter().copied())
+    }
+
+    /// Returns all lock classes whose name matches `name`, the paths in the class name do not have to be given
+    pub fn lock_classes_named(&self, name: &str) -> FxIndexSet<LockClass> {
+        let name = strip_paths(name);
+
+        self.invocations.values()
+            .map(|invocation| invocation.class)
+            .filter(|class| strip_paths(&self.lock_class_name(*class)) == name)
+            .collect()
+    }
+
+    /// Returns the index of the target whose lock method is called by the terminator
+    fn target_from_terminator(&self, terminator: &Terminator) -> Option<usize> {
+        let def_id = get_fn_def_id_from_terminator(terminator)?;
+
+        self.targets.iter()
+            .position(|target| target.lock_method == def_id)
+    }
+
+    fn lock_class_from_terminator(&mut self, mir_body: &Body<'tcx>, basic_block: BasicBlock) -> Option<(LockClass, usize)> {
+        let terminator = mir_body.basic_blocks[basic_block].terminator();
+
+        let target_index = self.target_from_terminator(terminator)?;
+        let target = &self.targets[target_index];
 
         let TerminatorKind::Call { args, .. } = &terminator.kind else {
             return None;
         };
 
+        if target.trait_method {
+            // the receiver can be any implementor of the trait, including generic parameters,
+            // so each receiver type is its own lock class
+            let arg_type = args.first()?.ty(&mir_body.local_decls, self.tcx);
+            let lock_type = if target.by_value {
+                arg_type
+            } else {
+                peel_lock_wrappers(self.tcx, arg_type)
+            };
+
+            let class = self.lock_class_ty_map.get_lock_class(self.tcx, lock_type, target.lock_method, lock_type, target_index);
+            return Some((class, target_index));
+        }
+
         // Find the first argument which is a Mutex, and use that mutex types generic arg to get the lock class
         for arg in args.iter() {
-            let arg_type = arg.ty(&mir_body.local_decls, self.tcx).peel_refs();
+            let arg_type = arg.ty(&mir_body.local_decls, self.tcx);
+            // by value lock methods are passed the lock itself, otherwise we are passed a reference to the lock
+            let arg_type = if target.by_value {
+                arg_type
+            } else {
+                peel_lock_wrappers(self.tcx, arg_type)
+            };
+
             let TyKind::Adt(adt_def, generic_args) = arg_type.kind() else {
                 continue;
             };
 
-            if adt_def.did() == self.pass_target.lock {
-                if generic_args.len() != 1 {
-                    // FIXME: don't panic here
-                    panic!("lockcheck only works on mutexes with 1 generic argument");
-                }
-
-                // FIXME: don't panic here
-                let generic_type = generic_args[0].expect_ty();
-                return Some(self.lock_class_ty_map.get_lock_class(generic_type));
+            if adt_def.did() == target.lock {
+                // `parse_target` checked the lock has a type parameter at this index
+                let generic_type = generic_args.types().nth(target.value_index)?;
+                let class = self.lock_class_ty_map.get_lock_class(self.tcx, arg_type, target.lock, generic_type, target_index);
+                return Some((class, target_index));
             }
         }
 
         None
     }
 
+    /// Returns the place the lock method's receiver borrows, if the reference was created just to call the lock method,
+    /// such as `self.state` in `self.state.lock()`
+    fn borrowed_lock_place(&self, mir_body: &Body<'tcx>, basic_block: BasicBlock, target_index: usize) -> Option<Place<'tcx>> {
+        let target = &self.targets[target_index];
+        if target.by_value || target.trait_method {
+            return None;
+        }
+
+        let TerminatorKind::Call { args, .. } = &mir_body.basic_blocks[basic_block].terminator().kind else {
+            return None;
+        };
+
+        let lock_ref = args.first()?.place()?;
+
+        // a reference held in a user variable can be locked more than once, even if it refers to a temporary
+        if !lock_ref.projection.is_empty() || mir_body.local_decls[lock_ref.local].is_user_variable() {
+            return None;
+        }
+
+        mir_body.basic_blocks.iter()
+            .flat_map(|block_data| block_data.statements.iter())
+            .find_map(|statement| match &statement.kind {
+                StatementKind::Assign(assign_data) if assign_data.0.local == lock_ref.local && assign_data.0.projection.is_empty() => {
+                    match assign_data.1 {
+                        Rvalue::Ref(_, _, place) => Some(place),
+                        _ => None,
+                    }
+                },
+                _ => None,
+            })
+    }
+
+    /// Returns true if the lock call in `basic_block` locks a temporary which was created by the lock constructor
+    /// just to be locked, such as in `Mutex::new(0).lock()`
+    fn locks_temporary(&self, mir_body: &Body<'tcx>, basic_block: BasicBlock, target_index: usize) -> bool {
+        let Some(borrowed_lock) = self.borrowed_lock_place(mir_body, basic_block, target_index) else {
+            return false;
+        };
+
+        if !borrowed_lock.projection.is_empty() || mir_body.local_decls[borrowed_lock.local].is_user_variable() {
+            return false;
+        }
+
+        // the temporary must be the return value of the lock constructor
+        let lock_constructor = self.targets[target_index].lock_constructor;
+        mir_body.basic_blocks.iter().any(|block_data| {
+            let terminator = block_data.terminator();
+            matches!(terminator.kind, TerminatorKind::Call { destination, .. } if destination.local == borrowed_lock.local && destination.projection.is_empty())
+                && get_fn_def_id_from_terminator(terminator) == Some(lock_constructor)
+        })
+    }
+
+    /// Returns where the lock is in the function's arguments if it is reached through fields or constant indexes,
+    /// such as `self.a` or `self.locks[0]`
+    fn lock_place(&self, mir_body: &Body<'tcx>, basic_block: BasicBlock, target_index: usize) -> Option<LockPlace> {
+        let borrowed_lock = self.borrowed_lock_place(mir_body, basic_block, target_index)?;
+
+        // every lock call reaching the lock through the same argument sees the same value, unless the argument is reassigned
+        if !mir_body.args_iter().any(|arg| arg == borrowed_lock.local) || Self::is_reassigned(mir_body, borrowed_lock.local) {
+            return None;
+        }
+
+        // optimized mir dereferences a box through the pointer in its fields, instead of the box itself
+        let mut in_box = false;
+        let projection = borrowed_lock.iter_projections()
+            .map(|(base, elem)| {
+                let box_deref = base.ty(mir_body, self.tcx).ty.is_box() || in_box;
+                in_box = box_deref && matches!(elem, ProjectionElem::Field(..));
+                (box_deref, elem)
+            })
+            .map(|(box_deref, elem)| match elem {
+                ProjectionElem::Deref if box_deref => Some(LockPlaceStep::BoxDeref),
+                ProjectionElem::Deref => Some(LockPlaceStep::Deref),
+                ProjectionElem::Field(field, _) => Some(LockPlaceStep::Field(field.as_usize())),
+                ProjectionElem::ConstantIndex { offset, from_end: false, .. } => Some(LockPlaceStep::Index(offset)),
+                // indexing with a constant such as `locks[0]` still uses a local holding the index
+                ProjectionElem::Index(index_local) => self.constant_index(mir_body, index_local).map(LockPlaceStep::Index),
+                // indexes which aren't constant could be any element
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(LockPlace {
+            local: borrowed_lock.local,
+            projection,
+        })
+    }
+
+    /// Returns true if the local, or a field of it, is assigned or mutably borrowed anywhere in the body
+    fn is_reassigned(mir_body: &Body<'tcx>, local: Local) -> bool {
+        // writes through a reference stored in the local don't change the local itself
+        let writes_local = |place: &Place<'tcx>| place.local == local && !place.is_indirect();
+
+        mir_body.basic_blocks.iter().any(|block_data| {
+            let assigned_in_statement = block_data.statements.iter().any(|statement| match &statement.kind {
+                StatementKind::Assign(assign_data) => writes_local(&assign_data.0) || match &assign_data.1 {
+                    Rvalue::Ref(_, BorrowKind::Mut { .. }, place) | Rvalue::AddressOf(Mutability::Mut, place) => writes_local(place),
+                    _ => false,
+                },
+                _ => false,
+            });
+
+            assigned_in_statement || matches!(
+                &block_data.terminator().kind,
+                TerminatorKind::Call { destination, .. } if writes_local(destination)
+            )
+        })
+    }
+
+    /// Returns the value of an index local if it is only ever assigned a constant
+    fn constant_index(&self, mir_body: &Body<'tcx>, index_local: Local) -> Option<u64> {
+        let mut assignments = mir_body.basic_blocks.iter()
+            .flat_map(|block_data| block_data.statements.iter())
+            .filter_map(|statement| match &statement.kind {
+                StatementKind::Assign(assign_data) if assign_data.0.local == index_local => Some(&assign_data.1),
+                _ => None,
+            });
+
+        let Some(Rvalue::Use(Operand::Constant(constant))) = assignments.next() else {
+            return None;
+        };
+        if assignments.next().is_some() {
+            return None;
+        }
+
+        constant.const_.try_eval_target_usize(self.tcx, ty::ParamEnv::reveal_all())
+    }
+
     fn collect_invocations_for_body(&mut self, def_id: DefId, mir_body: &Body<'tcx>) {
         for (basic_block, _) in reachable(mir_body) {
             let terminator = mir_body.basic_blocks[basic_block].terminator();
-            if let Some(lock_class) = self.lock_class_from_terminator(mir_body, basic_block) {
+            if let Some((lock_class, target)) = self.lock_class_from_terminator(mir_body, basic_block) {
                 let bbid = Bbid {
                     def_id,
                     basic_block,
                 };
 
-                self.invocations.insert(bbid, LockInvocation::new(lock_class, terminator.source_info.span));
+                let invocation = LockInvocation::new(
+                    lock_class,
+                    target,
+                    def_id,
+                    terminator.source_info.span,
+                    self.locks_temporary(mir_body, basic_block, target),
+                    self.lock_place(mir_body, basic_block, target),
+                );
+                self.invocations.insert(bbid, invocation);
             } else if let Some(called_fn_def_id) = get_fn_def_id_from_terminator(&terminator) {
                 // not a lock invocation, just record return location for regular function call
                 let TerminatorKind::Call { target, destination, .. } = terminator.kind else {
@@ -198,68 +653,182 @@ impl<'tcx> AnalysisPass<'tcx> {
     }
 
     fn collect_invocations(&mut self) {
-        let hir = self.tcx.hir();
-
         let lock_filler_symbol = Symbol::intern(LOCK_FILLER_FN_NAME);
 
-        for id in hir.items() {
-            let item = hir.item(id);
+        let mir_keys = self.tcx.mir_keys(());
+        let mut progress = Progress::new(self.options.progress, "bodies scanned for lock invocations", mir_keys.len());
+
+        for local_def_id in mir_keys.iter() {
+            progress.step();
+            let def_id = local_def_id.to_def_id();
 
-            // only functions have mir data to analyse
-            if !matches!(item.kind, ItemKind::Fn(..)) {
+            // only functions, closures, generators (such as async function bodies) and constant or static initializers are analysed
+            if !matches!(self.tcx.def_kind(def_id), DefKind::Fn | DefKind::AssocFn | DefKind::Closure)
+                && self.tcx.generator_kind(def_id).is_none()
+                && !self.tcx.is_const_initializer(def_id) {
                 continue;
             }
 
-            // ignore lock filler symbol inserted by lockcheck
-            if item.ident.name == lock_filler_symbol {
+            // ignore lock filler function inserted by lockcheck, and the closures inside of it
+            if self.tcx.opt_item_name(self.tcx.typeck_root_def_id(def_id)) == Some(lock_filler_symbol) {
                 continue;
             }
 
-            let def_id = item.owner_id.to_def_id();
-            let mir = self.tcx.optimized_mir(item.owner_id.to_def_id());
+            let Some(mir) = optimized_mir_or_skip(self.tcx, &self.skipped_bodies, def_id) else {
+                continue;
+            };
 
             self.collect_invocations_for_body(def_id, mir);
+            self.bodies.push(def_id);
+        }
+
+        progress.finish();
+
+        if self.config.analyze_deps {
+            self.collect_dependency_invocations();
+        }
+    }
+
+    /// Collects lock invocations in functions from other crates which are called from the local crate, used with `analyze_deps`
+    ///
+    /// Functions in `std`, `core` and `alloc` are skipped, since there are many of them and they can't lock user defined lock classes
+    fn collect_dependency_invocations(&mut self) {
+        let mut visited = HashSet::new();
+        let mut stack = self.bodies.clone();
+
+        while let Some(def_id) = stack.pop() {
+            let Some(mir_body) = self.tcx.try_optimized_mir(def_id) else {
+                continue;
+            };
+
+            for (basic_block, _) in reachable(mir_body) {
+                let Some(called_fn_def_id) = get_fn_def_id_from_terminator(mir_body.basic_blocks[basic_block].terminator()) else {
+                    continue;
+                };
+
+                if called_fn_def_id.is_local()
+                    || matches!(self.tcx.crate_name(called_fn_def_id.krate).as_str(), "std" | "core" | "alloc")
+                    || !visited.insert(called_fn_def_id) {
+                    continue;
+                }
+
+                let Some(called_mir_body) = optimized_mir_or_skip(self.tcx, &self.skipped_bodies, called_fn_def_id) else {
+                    continue;
+                };
+
+                self.collect_invocations_for_body(called_fn_def_id, called_mir_body);
+                self.bodies.push(called_fn_def_id);
+                stack.push(called_fn_def_id);
+            }
         }
     }
 
     /// Analyses collected lock invocations and determines their dependant lock classes
     fn collect_dependant_lock_classes(&mut self) {
+        let mut progress = Progress::new(self.options.progress, "lock invocations analysed", self.invocations.len());
+
         for (bbid, invocation) in self.invocations.iter() {
-            let mir_body = self.tcx.optimized_mir(bbid.def_id);
+            progress.step();
+            let Some(mir_body) = self.tcx.try_optimized_mir(bbid.def_id) else {
+                continue;
+            };
 
             let basic_block_data = &mir_body[bbid.basic_block];
-            let TerminatorKind::Call { target: Some(target), destination, .. } = basic_block_data.terminator().kind else {
+            let TerminatorKind::Call { target: Some(target), destination, ref args, .. } = basic_block_data.terminator().kind else {
                 panic!("lock invocation is expected to be call");
             };
 
-            let collector = DependantClassCollector::new(self.tcx, &self.invocations, &self.return_map);
-            let child_invocations = collector.collect(bbid.with_basic_block(target), destination.local);
+            let collector = DependantClassCollector::new(
+                self.tcx,
+                &self.config,
+                self.options.pessimistic,
+                &self.invocations,
+                &self.return_map,
+                &self.skipped_bodies,
+                &self.untracked_guards,
+            );
+            let target_info = &self.targets[invocation.target];
+            let (child_invocations, blocking_calls) = if target_info.held_during_closure {
+                collector.collect_closure_args(mir_body, args)
+            } else if target_info.held_during_init {
+                collector.collect_init_fn(mir_body, args, target_info.lock_constructor)
+            } else if target_info.is_async {
+                collector.collect_async(bbid.with_basic_block(target), &target_info.guards)
+            } else {
+                collector.collect(bbid.with_basic_block(target), destination.local, &target_info.guards)
+            };
             *invocation.child_invocations.borrow_mut() = child_invocations;
+            *invocation.blocking_calls.borrow_mut() = blocking_calls;
         }
+
+        progress.finish();
     }
 
     /// Creates a map for each lock class to which lock classes are called while the current lock class is locked
-    fn get_dependant_map(&self) -> HashMap<LockClass, HashSet<LockClass>> {
-        let mut dependant_map = HashMap::new();
+    fn get_dependant_map(&self) -> FxIndexMap<LockClass, FxIndexSet<LockClass>> {
+        let mut dependant_map = FxIndexMap::default();
 
         for invocation in self.invocations.values() {
-            let current_invocation_dependancies: &mut HashSet<LockClass> = dependant_map
+            let current_invocation_dependancies: &mut FxIndexSet<LockClass> = dependant_map
                 .entry(invocation.class)
                 .or_default();
 
-            for child_id in invocation.child_invocations.borrow().iter() {
+            for child_id in invocation.child_invocations.borrow().keys() {
                 let child_invocation = &self.invocations[child_id];
-                current_invocation_dependancies.insert(child_invocation.class);
+                if self.invocations_conflict(invocation, child_invocation) {
+                    current_invocation_dependancies.insert(child_invocation.class);
+                }
             }
         }
 
         dependant_map
     }
 
+    /// Returns false if `child_invocation` can never block while `invocation` is held,
+    /// which is the case for shared accesses of the same lock class,
+    /// and for locks of the same class where either lock is a temporary, since a temporary is a different instance than any other lock,
+    /// or where the locks are in different fields of the same argument
+    ///
+    /// Lock classes of types in `ignore_types` never conflict with anything,
+    /// and lock methods configured with `blocking = false` never block
+    fn invocations_conflict(&self, invocation: &LockInvocation, child_invocation: &LockInvocation) -> bool {
+        if self.lock_class_ty_map.is_ignored(invocation.class) || self.lock_class_ty_map.is_ignored(child_invocation.class) {
+            return false;
+        }
+
+        if !self.targets[child_invocation.target].blocking {
+            return false;
+        }
+
+        if invocation.class != child_invocation.class {
+            return true;
+        }
+
+        !invocation.transient
+            && !child_invocation.transient
+            && !invocation.locks_different_place(child_invocation)
+            && (!self.targets[invocation.target].shared || !self.targets[child_invocation.target].shared)
+    }
+
+    /// What happens when `invocation` is reached while a conflicting lock is held
+    fn conflict_kind(&self, invocation: &LockInvocation) -> ConflictKind {
+        if self.targets[invocation.target].panics {
+            ConflictKind::Panic
+        } else {
+            ConflictKind::Deadlock
+        }
+    }
+
+    /// The most severe configured severity of the targets of either invocation
+    fn severity(&self, invocation: &LockInvocation, other_invocation: &LockInvocation) -> Option<Severity> {
+        self.targets[invocation.target].severity
+            .max(self.targets[other_invocation.target].severity)
+    }
+
     fn dependancies_contain(
         target_class: LockClass,
         current_class: LockClass,
-        dependant_map: &HashMap<LockClass, HashSet<LockClass>>,
+        dependant_map: &FxIndexMap<LockClass, FxIndexSet<LockClass>>,
         visited_classes: &mut HashSet<LockClass>,
     ) -> bool {
         if visited_classes.contains(&current_class) {
@@ -281,35 +850,403 @@ impl<'tcx> AnalysisPass<'tcx> {
         return false;
     }
 
-    pub fn run_pass(&mut self, errors: &mut Errors<'tcx>) {
-        self.collect_invocations();
-        self.collect_dependant_lock_classes();
+    /// Points to the branches the deadlock depends on, if the child lock is only reached on some branches
+    fn branch_note(path: &PathInfo) -> Option<ErrorNote> {
+        if path.conditions.is_empty() {
+            return None;
+        }
+
+        // a condition in a loop can be on the path more than once
+        let mut labels: Vec<(Span, String)> = Vec::new();
+        for condition in path.conditions.iter() {
+            if !labels.iter().any(|(span, _)| *span == condition.span) {
+                labels.push((condition.span, "deadlock only occurs on some outcomes of this condition".to_owned()));
+            }
+        }
+
+        Some(ErrorNote {
+            message: "the lock is only held on some branches".to_owned(),
+            labels,
+        })
+    }
+
+    /// Points to the macro invocations which generated the lock calls of a deadlock
+    fn macro_note(&self, invocations: &[&LockInvocation]) -> Option<ErrorNote> {
+        let mut labels = Vec::new();
+        for invocation in invocations {
+            let Some(macro_expansion) = &invocation.macro_expansion else {
+                continue;
+            };
+
+            // both lock calls of a self deadlock can be in the same expansion
+            if !labels.iter().any(|(span, _)| *span == invocation.callsite) {
+                labels.push((invocation.callsite, format!(
+                    "lock class `{}` is locked in this expansion of `{}`",
+                    self.lock_class_name(invocation.class),
+                    macro_expansion,
+                )));
+            }
+        }
+
+        if labels.is_empty() {
+            return None;
+        }
+
+        Some(ErrorNote {
+            message: "in this macro expansion".to_owned(),
+            labels,
+        })
+    }
+
+    /// Notes that a lock class locked while it is held may be a different lock, if the guarded type contains locks of the same class
+    ///
+    /// This is the case for recursive data structures such as trees, where a parent and child node are locked hand over hand
+    fn recursive_class_note(&self, invocation: &LockInvocation, child_invocation: &LockInvocation) -> Option<ErrorNote> {
+        if invocation.class != child_invocation.class {
+            return None;
+        }
+
+        let lock_ty = self.lock_class_ty_map.get_ty(invocation.class);
+        let TyKind::Adt(_, generic_args) = lock_ty.kind() else {
+            return None;
+        };
+        let guarded_ty = generic_args.types().nth(self.targets[invocation.target].value_index)?;
+        let TyKind::Adt(guarded_adt_def, guarded_generic_args) = guarded_ty.kind() else {
+            return None;
+        };
+
+        let lock_ty = self.tcx.erase_regions(lock_ty);
+        let contains_lock = guarded_adt_def.all_fields()
+            .flat_map(|field| self.tcx.erase_regions(field.ty(self.tcx, guarded_generic_args)).walk())
+            .any(|arg| arg.as_type() == Some(lock_ty));
+
+        if !contains_lock {
+            return None;
+        }
+
+        Some(ErrorNote::new(format!(
+            "`{}` contains locks of the same class, so these may be different locks, such as a parent and child locked hand over hand; \
+            lockcheck doesn't tell lock instances apart, so this is only a deadlock if the same lock can be locked twice \
+            or locks of this class are also locked in the opposite order",
+            self.tcx.def_path_str(guarded_adt_def.did()),
+        )))
+    }
+
+    /// Creates a note pointing to where locks are acquired in the opposite order of an invocation and its child invocation
+    fn reverse_order_note(&self, invocation: &LockInvocation, child_invocation: &LockInvocation) -> Option<ErrorNote> {
+        // a lock class locked while it is already held has no opposite order
+        if invocation.class == child_invocation.class {
+            return None;
+        }
+
+        let (reverse_parent, reverse_child) = self.invocation_edges()
+            .filter(|(parent, child)| {
+                parent.class == child_invocation.class && (
+                    child.class == invocation.class || Self::dependancies_contain(
+                        invocation.class,
+                        child.class,
+                        &self.dependant_map,
+                        &mut HashSet::new(),
+                    )
+                )
+            })
+            // the opposite order in the same function is preferred, so the whole deadlock is shown in one place
+            .min_by_key(|(parent, child)| (!Self::same_function_reverse(invocation, parent, child), parent.span, child.span))?;
+
+        let message = if Self::same_function_reverse(invocation, &reverse_parent, &reverse_child) {
+            format!(
+                "lock classes are locked in both orders within `{}`, the opposite order is here",
+                self.tcx.def_path_str(invocation.def_id),
+            )
+        } else {
+            "lock classes are locked in the opposite order here".to_owned()
+        };
+
+        let (parent_span, child_span) = display_spans((reverse_parent.span, reverse_parent.callsite), (reverse_child.span, reverse_child.callsite));
+        Some(ErrorNote {
+            message,
+            labels: vec![
+                (parent_span, format!("lock class `{}` locked here", self.lock_class_name(reverse_parent.class))),
+                (child_span, format!(
+                    "lock class `{}` locked while `{}` is held",
+                    self.lock_class_name(reverse_child.class),
+                    self.lock_class_name(reverse_parent.class),
+                )),
+            ],
+        })
+    }
+
+    /// Returns true if the edge from `reverse_parent` to `reverse_child` locks back the class of `invocation`
+    /// in the same function as `invocation`, so both orders occur within one function
+    fn same_function_reverse(invocation: &LockInvocation, reverse_parent: &InvocationInfo, reverse_child: &InvocationInfo) -> bool {
+        reverse_parent.def_id == invocation.def_id && reverse_child.class == invocation.class
+    }
+
+    /// Reports deadlocks which only form a cycle when combined with lock orderings found in other crates
+    fn check_cross_crate_cycles(&self, errors: &mut Errors<'tcx>, shared_graph: &SharedGraph) {
+        let local_edges = self.dependant_map.iter()
+            .flat_map(|(parent, children)| children.iter().map(move |child| (*parent, *child)))
+            // ignored locks never form edges, so they can't be part of a cycle through another crate
+            .filter(|(parent, child)| !self.lock_class_ty_map.is_ignored(*parent) && !self.lock_class_ty_map.is_ignored(*child))
+            .map(|(parent, child)| (ty_name(self.lock_class_ty(parent)), ty_name(self.lock_class_ty(child))))
+            .collect::<Vec<_>>();
+
+        for invocation in self.invocations.values() {
+            for (child_id, path) in invocation.child_invocations.borrow().iter() {
+                let child_invocation = &self.invocations[child_id];
+                if !self.invocations_conflict(invocation, child_invocation) {
+                    continue;
+                }
+
+                // cycles using only local edges are already reported by the regular deadlock check
+                if Self::dependancies_contain(invocation.class, child_invocation.class, &self.dependant_map, &mut HashSet::new()) {
+                    continue;
+                }
+
+                let Some(edge) = shared_graph.find_cross_crate_path(
+                    &ty_name(self.lock_class_ty(child_invocation.class)),
+                    &ty_name(self.lock_class_ty(invocation.class)),
+                    &local_edges,
+                ) else {
+                    continue;
+                };
+
+                let note = format!(
+                    "lock class `{}` is locked while lock class `{}` is held in crate `{}` at {}",
+                    edge.child,
+                    edge.parent,
+                    edge.crate_name,
+                    edge.location,
+                );
+
+                errors.emit_deadlock_error(DeadlockError {
+                    parent_invocation: self.get_invocation_error_info(child_invocation),
+                    child_invocation: self.get_invocation_error_info(invocation),
+                    kind: self.conflict_kind(child_invocation),
+                    confidence: path.confidence(),
+                    severity: self.severity(invocation, child_invocation),
+                    notes: vec![ErrorNote::new(note)],
+                });
+            }
+        }
+    }
+
+    /// Reports every place a lock is acquired while a lock it is configured to never be nested in is held
+    fn check_forbidden_orders(&self, errors: &mut Errors<'tcx>) {
+        for order in self.config.forbidden_orders.iter() {
+            let held_classes = self.lock_classes_named(&order.held);
+            let locked_classes = self.lock_classes_named(&order.locked);
+
+            for invocation in self.invocations.values().filter(|invocation| held_classes.contains(&invocation.class)) {
+                for (child_id, path) in invocation.child_invocations.borrow().iter() {
+                    let child_invocation = &self.invocations[child_id];
+                    if !locked_classes.contains(&child_invocation.class)
+                        || self.lock_class_ty_map.is_ignored(invocation.class)
+                        || self.lock_class_ty_map.is_ignored(child_invocation.class) {
+                        continue;
+                    }
 
-        let dependant_map = self.get_dependant_map();
+                    errors.emit_deadlock_error(DeadlockError {
+                        parent_invocation: self.get_invocation_error_info(invocation),
+                        child_invocation: self.get_invocation_error_info(child_invocation),
+                        kind: ConflictKind::ForbiddenOrder,
+                        confidence: path.confidence(),
+                        severity: self.severity(invocation, child_invocation),
+                        notes: [ErrorNote::new(format!(
+                            "`{}` must never be locked while `{}` is held",
+                            order.locked,
+                            order.held,
+                        ))]
+                            .into_iter()
+                            .chain(self.macro_note(&[invocation, child_invocation]))
+                            .collect(),
+                    });
+                }
+            }
+        }
+    }
 
+    /// Reports every place a lock is acquired while a lock with a higher configured rank is held
+    fn check_lock_ranks(&self, errors: &mut Errors<'tcx>) {
         for invocation in self.invocations.values() {
-            for child_id in invocation.child_invocations.borrow().iter() {
+            let Some(held_rank) = self.targets[invocation.target].rank else {
+                continue;
+            };
+
+            for (child_id, path) in invocation.child_invocations.borrow().iter() {
                 let child_invocation = &self.invocations[child_id];
+                let Some(locked_rank) = self.targets[child_invocation.target].rank else {
+                    continue;
+                };
+
+                if locked_rank >= held_rank
+                    || self.lock_class_ty_map.is_ignored(invocation.class)
+                    || self.lock_class_ty_map.is_ignored(child_invocation.class) {
+                    continue;
+                }
+
+                errors.emit_deadlock_error(DeadlockError {
+                    parent_invocation: self.get_invocation_error_info(invocation),
+                    child_invocation: self.get_invocation_error_info(child_invocation),
+                    kind: ConflictKind::RankViolation,
+                    confidence: path.confidence(),
+                    severity: self.severity(invocation, child_invocation),
+                    notes: [ErrorNote::new(format!(
+                        "`{}` has rank {}, which is lower than the rank {} of `{}`, so it must be locked first",
+                        self.lock_class_name(child_invocation.class),
+                        locked_rank,
+                        held_rank,
+                        self.lock_class_name(invocation.class),
+                    ))]
+                        .into_iter()
+                        .chain(self.macro_note(&[invocation, child_invocation]))
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    pub fn run_pass(&mut self, errors: &mut Errors<'tcx>, shared_graph: &SharedGraph) {
+        self.collect_invocations();
+        if self.invocations.is_empty() && !self.targets.is_empty() {
+            errors.emit_no_invocations_warning();
+        }
+
+        self.collect_dependant_lock_classes();
+
+        self.dependant_map = self.get_dependant_map();
+
+        // go through invocations in source order so a cycle is always reported at the same place
+        let mut invocations = self.invocations.values().collect::<Vec<_>>();
+        invocations.sort_by_key(|invocation| invocation.span);
+
+        // cycles between 2 different lock classes are only reported once
+        let mut reported_class_pairs = HashSet::new();
+
+        let safe_pairs = self.config.safe_pairs.iter()
+            .map(|order| (self.lock_classes_named(&order.held), self.lock_classes_named(&order.locked)))
+            .collect::<Vec<_>>();
+        let is_safe_pair = |a: LockClass, b: LockClass| safe_pairs.iter().any(|(held, locked)| {
+            (held.contains(&a) && locked.contains(&b)) || (held.contains(&b) && locked.contains(&a))
+        });
+
+        let entry_points = self.options.entry_points.then(|| EntryPoints::new(self.tcx, &self.bodies));
+
+        for invocation in invocations {
+            let child_ids = invocation.child_invocations.borrow();
+            let mut children = child_ids.iter()
+                .map(|(child_id, path)| (&self.invocations[child_id], path))
+                .collect::<Vec<_>>();
+            children.sort_by_key(|(child_invocation, _)| child_invocation.span);
+
+            for (child_invocation, path) in children {
+                if !self.invocations_conflict(invocation, child_invocation) {
+                    continue;
+                }
 
                 let mut visited_classes = HashSet::new();
                 if Self::dependancies_contain(
                     invocation.class,
                     child_invocation.class,
-                    &dependant_map,
+                    &self.dependant_map,
                     &mut visited_classes,
                 ) {
-                    let child_error = self.lock_class_ty_map.get_invocation_error_info(invocation);
-                    let parent_error = self.lock_class_ty_map.get_invocation_error_info(child_invocation);
-                    errors.emit_deadlock_error(parent_error, child_error);
+                    let class_pair = (invocation.class != child_invocation.class).then(|| (
+                        invocation.class.min(child_invocation.class),
+                        invocation.class.max(child_invocation.class),
+                    ));
+
+                    if class_pair.is_some_and(|class_pair| reported_class_pairs.contains(&class_pair)) {
+                        continue;
+                    }
+
+                    if is_safe_pair(invocation.class, child_invocation.class) {
+                        reported_class_pairs.extend(class_pair);
+                        errors.record_suppressed("safe_pairs");
+                        continue;
+                    }
+
+                    // the pair is only marked as reported once an edge of it is reported,
+                    // so a cycle is still reported at its other edge if this one is filtered by `--min-confidence` or `--changed-files`
+                    let reported = errors.emit_deadlock_error(DeadlockError {
+                        parent_invocation: self.get_invocation_error_info(child_invocation),
+                        child_invocation: self.get_invocation_error_info(invocation),
+                        kind: self.conflict_kind(child_invocation),
+                        confidence: path.confidence(),
+                        severity: self.severity(invocation, child_invocation),
+                        notes: self.reverse_order_note(invocation, child_invocation)
+                            .into_iter()
+                            .chain(self.recursive_class_note(invocation, child_invocation))
+                            .chain(Self::branch_note(path))
+                            .chain(self.macro_note(&[child_invocation, invocation]))
+                            .chain(entry_points.as_ref().map(|entry_points| entry_points.note(&[invocation.def_id, child_invocation.def_id])))
+                            .collect(),
+                    });
+
+                    if reported {
+                        reported_class_pairs.extend(class_pair);
+                    }
                 }
             }
+
+            for blocking_call in invocation.blocking_calls.borrow().iter() {
+                errors.emit_blocking_call_warning(BlockingCallWarning {
+                    invocation: self.get_invocation_error_info(invocation),
+                    call_span: blocking_call.span,
+                    function: blocking_call.function.clone(),
+                });
+            }
+        }
+
+        self.check_cross_crate_cycles(errors, shared_graph);
+        self.check_forbidden_orders(errors);
+        self.check_lock_ranks(errors);
+
+        if self.options.perf_hints {
+            self.check_locks_in_loops(errors);
+        }
+
+        if self.options.skipped_bodies {
+            for (def_id, reason) in self.skipped_bodies.borrow().iter() {
+                let path = with_no_trimmed_paths!(self.tcx.def_path_str(*def_id));
+                errors.emit_skipped_body(path, *reason);
+            }
+        }
+
+        if self.options.untracked_guards {
+            for (span, reason) in self.untracked_guards.borrow().iter() {
+                errors.emit_untracked_guard(*span, reason);
+            }
+        }
+    }
+
+    /// Reports every lock invocation which is run again each time a loop repeats
+    fn check_locks_in_loops(&self, errors: &mut Errors<'tcx>) {
+        for (bbid, invocation) in self.invocations.iter() {
+            let Some(mir_body) = self.tcx.try_optimized_mir(bbid.def_id) else {
+                continue;
+            };
+
+            if block_in_loop(mir_body, bbid.basic_block) {
+                errors.emit_perf_hint(PerfHint {
+                    invocation: self.get_invocation_error_info(invocation),
+                });
+            }
         }
     }
 }
 
+/// A call to a configured blocking function or an await point reached while a lock is held
+#[derive(Debug, Clone)]
+struct BlockingCall {
+    span: Span,
+    /// Path of the blocking function, or `None` for an await point
+    function: Option<String>,
+}
+
 /// A map from a function definition id to all the basic blocks it might return to
 #[derive(Debug, Default)]
-struct FunctionReturnMap(HashMap<DefId, HashSet<ReturnLocation>>);
+struct FunctionReturnMap(HashMap<DefId, FxIndexSet<ReturnLocation>>);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct ReturnLocation {
@@ -324,15 +1261,78 @@ impl FunctionReturnMap {
             .insert(return_location);
     }
 
-    fn iter_return_locations<'a>(&'a self, fn_def_id: DefId) -> impl Iterator<Item = ReturnLocation> + 'a {
-        self.0[&fn_def_id].iter().copied()
+    fn iter_return_locations<'a>(&'a self, fn_def_id: DefId) -> impl Iterator<Item = ReturnLocation> + 'a {
+        self.0[&fn_def_id].iter().copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct LocalBlockPair {
+    block: Bbid,
+    local: Local,
+}
+
+/// An outcome of a branch which is taken on a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    /// Block ending with the branch
+    block: Bbid,
+    /// Block the branch goes to on this outcome
+    target: BasicBlock,
+    /// Span of the branch condition
+    pub span: Span,
+}
+
+/// Information about the path taken from a lock invocation to one of its child invocations
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PathInfo {
+    /// Branch conditions which must hold for the path to be taken, in the order they are reached
+    pub conditions: Vec<Condition>,
+    /// Set if the path relies on assumptions about function calls which could not be analysed
+    pub assumed: bool,
+}
+
+impl PathInfo {
+    fn with_condition(&self, condition: Condition) -> Self {
+        let mut path = self.clone();
+        path.conditions.push(condition);
+        path
+    }
+
+    /// Removes the conditions which `other` took a different outcome of,
+    /// the block both paths lead to is reached whichever way those branches go
+    fn merge(&self, other: &PathInfo) -> Self {
+        let conditions = self.conditions.iter()
+            .filter(|condition| !other.conditions.iter().any(|other_condition| {
+                other_condition.block == condition.block && other_condition.target != condition.target
+            }))
+            .copied()
+            .collect();
+
+        PathInfo {
+            conditions,
+            assumed: self.assumed || other.assumed,
+        }
+    }
+
+    /// How likely it is that the path is taken, as a percentage
+    pub fn confidence(&self) -> u8 {
+        let confidence = 100 / (self.conditions.len() + 1);
+
+        if self.assumed {
+            (confidence / 2) as u8
+        } else {
+            confidence as u8
+        }
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct LocalBlockPair {
-    block: Bbid,
-    local: Local,
+    fn most_likely(self, other: &PathInfo) -> Self {
+        if other.confidence() > self.confidence() {
+            other.clone()
+        } else {
+            self
+        }
+    }
 }
 
 /// Indicates what happed to a lock guard passed in a function
@@ -355,37 +1355,343 @@ impl GuardState {
 
 struct DependantClassCollector<'a, 'tcx> {
     tcx: TyCtxt<'tcx>,
-    invocation_map: &'a HashMap<Bbid, LockInvocation>,
+    config: &'a Config,
+    pessimistic: bool,
+    invocation_map: &'a FxIndexMap<Bbid, LockInvocation>,
     return_map: &'a FunctionReturnMap,
-    dependant_classes: HashSet<Bbid>,
-    visited_blocks: HashSet<LocalBlockPair>,
+    skipped_bodies: &'a RefCell<FxIndexMap<DefId, MissingMir>>,
+    untracked_guards: &'a RefCell<FxIndexMap<Span, &'static str>>,
+    dependant_classes: FxIndexMap<Bbid, PathInfo>,
+    blocking_calls: Vec<BlockingCall>,
+    /// Locals which hold a mutable reference to the guard, the guard could be released through these
+    guard_references: HashSet<(DefId, Local)>,
+    /// Blocks which were traversed with each guard local, and the path they were traversed with
+    visited_blocks: HashMap<LocalBlockPair, PathInfo>,
+    /// Number of different guard locals each block has been visited with
+    block_visits: HashMap<Bbid, usize>,
     // Functions which are visited without looking for a particular lock guard being dropped
-    visited_functions: HashSet<DefId>,
+    //
+    // This stores the most likely path each function was visited with, a function is visited again
+    // if it is reached by a more likely path, which can happen with recursion between multiple functions
+    visited_functions: HashMap<DefId, PathInfo>,
+    /// Call in the crate's own code which the dependency being analysed was reached through
+    ///
+    /// With `local_only`, blocking calls and await points in dependencies are reported here
+    local_frame_call: Option<Span>,
+    /// Guard types of the lock, the guard is still held if it is passed to a function which returns one of these
+    guards: &'a [DefId],
 }
 
 impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
-    fn new(tcx: TyCtxt<'tcx>, invocation_map: &'a HashMap<Bbid, LockInvocation>, return_map: &'a FunctionReturnMap) -> Self {
+    fn new(
+        tcx: TyCtxt<'tcx>,
+        config: &'a Config,
+        pessimistic: bool,
+        invocation_map: &'a FxIndexMap<Bbid, LockInvocation>,
+        return_map: &'a FunctionReturnMap,
+        skipped_bodies: &'a RefCell<FxIndexMap<DefId, MissingMir>>,
+        untracked_guards: &'a RefCell<FxIndexMap<Span, &'static str>>,
+    ) -> Self {
         DependantClassCollector {
             tcx,
+            config,
+            pessimistic,
             invocation_map,
             return_map,
-            dependant_classes: HashSet::new(),
-            visited_blocks: HashSet::new(),
-            visited_functions: HashSet::new(),
+            skipped_bodies,
+            untracked_guards,
+            dependant_classes: FxIndexMap::default(),
+            blocking_calls: Vec::new(),
+            guard_references: HashSet::new(),
+            visited_blocks: HashMap::new(),
+            block_visits: HashMap::new(),
+            visited_functions: HashMap::new(),
+            local_frame_call: None,
+            guards: &[],
+        }
+    }
+
+    fn collect(mut self, basic_block_id: Bbid, lock_local: Local, guards: &'a [DefId]) -> (FxIndexMap<Bbid, PathInfo>, Vec<BlockingCall>) {
+        self.guards = guards;
+        self.collect_inner(basic_block_id, lock_local, None, true, PathInfo::default());
+
+        let Self { dependant_classes, blocking_calls, .. } = self;
+        (dependant_classes, blocking_calls)
+    }
+
+    /// Collects the lock invocations in the body of every closure passed as an argument
+    /// 
+    /// This is used for locks which are held while a closure passed to the lock method runs
+    fn collect_closure_args(mut self, mir_body: &Body<'tcx>, args: &[Operand<'tcx>]) -> (FxIndexMap<Bbid, PathInfo>, Vec<BlockingCall>) {
+        self.collect_callbacks_in_args(mir_body, args, &PathInfo::default());
+
+        let Self { dependant_classes, blocking_calls, .. } = self;
+        (dependant_classes, blocking_calls)
+    }
+
+    /// Collects the lock invocations in the init function of the lock passed to the lock method
+    ///
+    /// This is used for locks such as `LazyLock`, which are held while the init function given to their constructor runs
+    fn collect_init_fn(mut self, mir_body: &Body<'tcx>, args: &[Operand<'tcx>], constructor: DefId) -> (FxIndexMap<Bbid, PathInfo>, Vec<BlockingCall>) {
+        for arg in args.iter() {
+            if let Some(init_fn_def_id) = self.resolve_init_fn(mir_body, arg, constructor) {
+                self.collect_all_invocations(init_fn_def_id, &PathInfo::default());
+            }
+        }
+
+        let Self { dependant_classes, blocking_calls, .. } = self;
+        (dependant_classes, blocking_calls)
+    }
+
+    /// Collects lock invocations reachable from `start` in the same function until the guard is dropped
+    ///
+    /// This is used for async locks, the guard only exists once the future returned by the lock method is ready,
+    /// so it can't be followed from the return value of the lock method like other guards.
+    fn collect_async(mut self, start: Bbid, guards: &[DefId]) -> (FxIndexMap<Bbid, PathInfo>, Vec<BlockingCall>) {
+        self.collect_reachable(start, guards, &PathInfo::default());
+
+        let Self { dependant_classes, blocking_calls, .. } = self;
+        (dependant_classes, blocking_calls)
+    }
+
+    /// Collects lock invocations in every block reachable from `start` in the same function,
+    /// stopping where the guard is dropped or moved into a function
+    ///
+    /// The guard is the first value with one of the `guards` types written after `start`,
+    /// for async locks this is where it is taken out of the lock future's output.
+    /// Branches on whether a future is ready are not conditions, since every await point has one
+    fn collect_reachable(&mut self, start: Bbid, guards: &[DefId], path: &PathInfo) {
+        let Some(mir_body) = optimized_mir_or_skip(self.tcx, self.skipped_bodies, start.def_id) else {
+            return;
+        };
+
+        let mut visited: HashMap<BasicBlock, PathInfo> = HashMap::new();
+        let mut stack = vec![(start.basic_block, None, path.clone())];
+
+        while let Some((basic_block, mut guard_place, mut path)) = stack.pop() {
+            if mir_body[basic_block].is_cleanup {
+                continue;
+            }
+
+            // same as in `collect_inner`, a block is only visited again if it is reached by a more likely path
+            if let Some(visited_path) = visited.get(&basic_block) {
+                let merged_path = visited_path.merge(&path).most_likely(&path);
+                if merged_path.confidence() <= visited_path.confidence() {
+                    continue;
+                }
+                path = merged_path;
+            }
+            visited.insert(basic_block, path.clone());
+
+            let bbid = start.with_basic_block(basic_block);
+            if self.invocation_map.contains_key(&bbid) {
+                self.record_dependant(bbid, &path);
+            }
+
+            for statement in mir_body[basic_block].statements.iter() {
+                guard_place = self.guard_place_after_statement(mir_body, statement, guard_place, guards);
+            }
+
+            let terminator = mir_body[basic_block].terminator();
+            match &terminator.kind {
+                TerminatorKind::Drop { place, .. } if guard_place == Some(*place) => continue,
+                TerminatorKind::Drop { place, .. } => self.collect_destructor(mir_body, place, &path),
+                TerminatorKind::Call { args, destination, .. } => {
+                    let moves_guard = args.iter().any(|arg| matches!(
+                        arg,
+                        Operand::Move(place) if guard_place == Some(*place)
+                    ));
+
+                    if moves_guard {
+                        // if another guard of the lock is returned, the guard was converted (such as by downgrading a write guard),
+                        // otherwise assume the guard is dropped by the function it is moved into
+                        if !self.is_guard_place(mir_body, destination, guards) {
+                            continue;
+                        }
+                        guard_place = Some(*destination);
+                    } else if guard_place.is_none() && self.is_guard_place(mir_body, destination, guards) {
+                        guard_place = Some(*destination);
+                    }
+
+                    if let Some(fn_def_id) = get_fn_def_id_from_terminator(terminator) {
+                        self.record_blocking_call(start.def_id, fn_def_id, terminator.source_info.span);
+                        self.in_callee(start.def_id, fn_def_id, terminator.source_info.span, |collector| {
+                            collector.collect_all_invocations(fn_def_id, &path)
+                        });
+                    }
+                },
+                TerminatorKind::SwitchInt { discr, targets } if !self.switches_on_poll(mir_body, basic_block, discr) => {
+                    // branches which only lead to a panic don't make the other branches conditional
+                    let continuing_branches = targets.all_targets()
+                        .iter()
+                        .filter(|target| !block_diverges(mir_body, **target))
+                        .count();
+
+                    for target in targets.all_targets() {
+                        let branch_path = if continuing_branches > 1 {
+                            path.with_condition(Condition {
+                                block: bbid,
+                                target: *target,
+                                span: terminator.source_info.span,
+                            })
+                        } else {
+                            path.clone()
+                        };
+
+                        stack.push((*target, guard_place, branch_path));
+                    }
+                    continue;
+                },
+                _ => (),
+            }
+
+            stack.extend(terminator.successors().map(|target| (target, guard_place, path.clone())));
+        }
+    }
+
+    /// Returns the place the guard is in after the statement
+    ///
+    /// Until the guard is found, this is the first place a value with one of the `guards` types is written to
+    fn guard_place_after_statement(
+        &self,
+        mir_body: &Body<'tcx>,
+        statement: &Statement<'tcx>,
+        guard_place: Option<Place<'tcx>>,
+        guards: &[DefId],
+    ) -> Option<Place<'tcx>> {
+        let StatementKind::Assign(assign_data) = &statement.kind else {
+            return guard_place;
+        };
+        let (destination, rvalue) = &**assign_data;
+
+        match (guard_place, rvalue) {
+            (Some(guard_place), Rvalue::Use(Operand::Move(place) | Operand::Copy(place))) if *place == guard_place => Some(*destination),
+            (None, _) if self.is_guard_place(mir_body, destination, guards) => Some(*destination),
+            _ => guard_place,
+        }
+    }
+
+    /// Returns true if the switch at the end of the block branches on whether a future is ready
+    fn switches_on_poll(&self, mir_body: &Body<'tcx>, basic_block: BasicBlock, discr: &Operand<'tcx>) -> bool {
+        let Some(discr_place) = discr.place() else {
+            return false;
+        };
+
+        mir_body[basic_block].statements.iter().any(|statement| {
+            let StatementKind::Assign(assign_data) = &statement.kind else {
+                return false;
+            };
+
+            match &assign_data.1 {
+                Rvalue::Discriminant(place) if assign_data.0 == discr_place => {
+                    place.ty(&mir_body.local_decls, self.tcx).ty.ty_adt_def()
+                        .is_some_and(|adt_def| self.tcx.lang_items().get(LangItem::Poll) == Some(adt_def.did()))
+                },
+                _ => false,
+            }
+        })
+    }
+
+    /// Returns true if `place` has the type of one of the guards
+    fn is_guard_place(&self, mir_body: &Body<'tcx>, place: &Place<'tcx>, guards: &[DefId]) -> bool {
+        let ty = place.ty(&mir_body.local_decls, self.tcx).ty;
+
+        match ty.ty_adt_def() {
+            Some(adt_def) => guards.contains(&adt_def.did()),
+            None => false,
+        }
+    }
+
+    /// Runs `f` to analyse a call from `caller` to `callee` at `call_span`,
+    /// keeping track of the last call in the crate's own code while `f` analyses dependencies
+    fn in_callee<T>(&mut self, caller: DefId, callee: DefId, call_span: Span, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous_call = self.local_frame_call;
+        if caller.is_local() && !callee.is_local() {
+            self.local_frame_call = Some(call_span);
+        }
+
+        let out = f(self);
+        self.local_frame_call = previous_call;
+        out
+    }
+
+    /// Returns the span a blocking call or await point at `span` in `caller` is reported at,
+    /// or `None` if it is in a dependency which wasn't reached from the crate's own code
+    fn reported_span(&self, caller: DefId, span: Span) -> Option<Span> {
+        if self.config.local_only && !caller.is_local() {
+            self.local_frame_call
+        } else {
+            Some(span)
+        }
+    }
+
+    /// Records a call to `fn_def_id` at `span` in `caller` if the function is configured as blocking
+    fn record_blocking_call(&mut self, caller: DefId, fn_def_id: DefId, span: Span) {
+        let Some(span) = self.reported_span(caller, span) else {
+            return;
+        };
+
+        let function = with_no_trimmed_paths!(self.tcx.def_path_str(fn_def_id));
+        if self.config.is_blocking_function(&function) && !self.blocking_calls.iter().any(|call| call.span == span) {
+            self.blocking_calls.push(BlockingCall {
+                span,
+                function: Some(function),
+            });
+        }
+    }
+
+    /// Records an await point reached while a guard which is not async aware is held
+    fn record_await(&mut self, caller: DefId, span: Span) {
+        let Some(span) = self.reported_span(caller, span) else {
+            return;
+        };
+
+        if !self.blocking_calls.iter().any(|call| call.span == span) {
+            self.blocking_calls.push(BlockingCall {
+                span,
+                function: None,
+            });
+        }
+    }
+
+    /// Records the destination of `statement` if it is a mutable reference to the guard or to another reference to the guard
+    fn record_guard_reference(&mut self, def_id: DefId, statement: &Statement, current_local: Local) {
+        let StatementKind::Assign(assign_data) = &statement.kind else {
+            return;
+        };
+
+        let (Rvalue::Ref(_, BorrowKind::Mut { .. }, place) | Rvalue::AddressOf(Mutability::Mut, place)) = &assign_data.1 else {
+            return;
+        };
+
+        if place.local == current_local || self.guard_references.contains(&(def_id, place.local)) {
+            self.guard_references.insert((def_id, assign_data.0.local));
         }
     }
 
-    fn collect(mut self, basic_block_id: Bbid, lock_local: Local) -> HashSet<Bbid> {
-        self.collect_inner(basic_block_id, lock_local, true);
+    fn record_dependant(&mut self, bbid: Bbid, path: &PathInfo) {
+        let is_most_likely = self.dependant_classes.get(&bbid)
+            .map_or(true, |old_path| path.confidence() > old_path.confidence());
 
-        let Self { dependant_classes, .. } = self;
-        dependant_classes
+        if is_most_likely {
+            self.dependant_classes.insert(bbid, path.clone());
+        }
     }
 
-    fn collect_inner(&mut self, basic_block_id: Bbid, mut current_local: Local, examine_returns: bool) -> GuardState {
+    /// Follows the guard in `current_local` from the start of the block until it is dropped or the function returns
+    ///
+    /// If the guard was moved into an aggregate, `guard_field` is the field of `current_local` it is in
+    fn collect_inner(
+        &mut self,
+        basic_block_id: Bbid,
+        mut current_local: Local,
+        mut guard_field: Option<FieldIdx>,
+        examine_returns: bool,
+        mut path: PathInfo,
+    ) -> GuardState {
         let mut basic_block = basic_block_id.basic_block;
         let mut guard_state = GuardState::Undetermined;
-        let Some(mir_body) = self.tcx.try_optimized_mir(basic_block_id.def_id) else {
+        let Some(mir_body) = optimized_mir_or_skip(self.tcx, self.skipped_bodies, basic_block_id.def_id) else {
             // if we cannot get mir, say it is underetmined
             return GuardState::Undetermined;
         };
@@ -398,37 +1704,80 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                 block: current_bbid,
                 local: current_local,
             };
-            if self.visited_blocks.contains(&local_block_pair) {
-                return GuardState::Undetermined;
+            // unless it is reached by a more likely path, such as through another outcome of a branch it was already reached through,
+            // where the rest of the path no longer depends on that branch
+            if let Some(visited_path) = self.visited_blocks.get(&local_block_pair) {
+                let merged_path = visited_path.merge(&path).most_likely(&path);
+                if merged_path.confidence() <= visited_path.confidence() {
+                    return GuardState::Undetermined;
+                }
+                path = merged_path;
+            } else {
+                // a block reached with many different locals is where flows converge after the guard was moved around,
+                // lock invocations in it were already recorded, so it isn't traversed again for every local
+                let visits = self.block_visits.entry(current_bbid).or_default();
+                if *visits >= MAX_LOCALS_PER_BLOCK {
+                    return guard_state.combine(GuardState::Undetermined);
+                }
+                *visits += 1;
             }
-            self.visited_blocks.insert(local_block_pair);
+            self.visited_blocks.insert(local_block_pair, path.clone());
 
             // mark dependant class if this current block also is a lock invocation
             if self.invocation_map.contains_key(&current_bbid) {
-                self.dependant_classes.insert(current_bbid);
+                self.record_dependant(current_bbid, &path);
             }
 
             let basic_block_data = &mir_body[basic_block];
 
             for statement in basic_block_data.statements.iter() {
-                current_local = calculate_new_local_after_statement(statement, current_local);
+                self.record_guard_reference(basic_block_id.def_id, statement, current_local);
+                if overwrites_guard(statement, current_local) {
+                    // assigning a new value to the guard's variable drops the old guard
+                    return guard_state.combine(GuardState::Dropped);
+                }
+
+                let Some((new_local, new_guard_field)) = calculate_new_local_after_statement(statement, current_local, guard_field) else {
+                    self.untracked_guards.borrow_mut().entry(statement.source_info.span).or_insert("local of lock guard reused while not dropped");
+                    return guard_state.combine(GuardState::Undetermined);
+                };
+                current_local = new_local;
+                guard_field = new_guard_field;
             }
 
             match &basic_block_data.terminator().kind {
                 TerminatorKind::Goto { target } => basic_block = *target,
                 TerminatorKind::SwitchInt { targets, .. } => {
+                    // branches which only lead to a panic (such as from unwrap) don't make the other branches conditional
+                    let continuing_branches = targets.all_targets()
+                        .iter()
+                        .filter(|target| !block_diverges(mir_body, **target))
+                        .count();
+                    let branch_path = |target| if continuing_branches > 1 {
+                        path.with_condition(Condition {
+                            block: current_bbid,
+                            target,
+                            span: basic_block_data.terminator().source_info.span,
+                        })
+                    } else {
+                        path.clone()
+                    };
+
                     for (_, target) in targets.iter() {
                         // this runs for each branch except the otherwise
                         guard_state = guard_state.combine(
                             self.collect_inner(
                                 basic_block_id.with_basic_block(target),
                                 current_local,
+                                guard_field,
                                 examine_returns,
+                                branch_path(target),
                             )
                         );
                     }
 
                     // now we run for the otherwise branch
+                    path = branch_path(targets.otherwise());
                     basic_block = targets.otherwise();
                 },
                 TerminatorKind::UnwindResume => return guard_state.combine(GuardState::Undetermined),
@@ -441,12 +1790,21 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                                 self.collect_inner(
                                     return_location.return_bbid,
                                     return_location.return_local,
+                                    None,
                                     true,
+                                    path.clone(),
                                 )
                             );
                         }
 
                         return guard_state;
+                    } else if self.tcx.generator_kind(basic_block_id.def_id).is_some() {
+                        // the guard is stored in the generator while it is suspended at an await point
+                        self.record_await(basic_block_id.def_id, basic_block_data.terminator().source_info.span);
+                        return guard_state.combine(GuardState::Undetermined);
+                    } else if self.tcx.is_const_initializer(basic_block_id.def_id) {
+                        // guards can't have drop glue in const contexts, so they are just left at the end of the initializer
+                        return guard_state.combine(GuardState::Dropped);
                     } else {
                         panic!("function returned while guard not dropped");
                     }
@@ -456,13 +1814,24 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                     // if current local is the return place
                     if current_local == Local::from_u32(0) {
                         return guard_state.combine(GuardState::Returned);
+                    } else if self.tcx.generator_kind(basic_block_id.def_id).is_some() {
+                        // the guard is stored in the generator while it is suspended at an await point
+                        self.record_await(basic_block_id.def_id, basic_block_data.terminator().source_info.span);
+                        return guard_state.combine(GuardState::Undetermined);
+                    } else if self.tcx.is_const_initializer(basic_block_id.def_id) {
+                        // guards can't have drop glue in const contexts, so they are just left at the end of the initializer
+                        return guard_state.combine(GuardState::Dropped);
                     } else {
                         panic!("function returned while guard not dropped");
                     }
                 },
                 TerminatorKind::Unreachable => return guard_state.combine(GuardState::Undetermined),
                 TerminatorKind::Drop { place, target, .. } => {
-                    if place.local == current_local {
+                    // the destructor runs before the value's fields are dropped, so if the guard is
+                    // wrapped in a type with a `Drop` impl, the guard is still held while that runs
+                    self.collect_destructor(mir_body, place, &path);
+
+                    if contains_guard(place, current_local, guard_field) {
                         return guard_state.combine(GuardState::Dropped);
                     } else {
                         basic_block = *target;
@@ -470,7 +1839,8 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                 },
                 TerminatorKind::Call { args, destination, target, .. } => {
                     if destination.local == current_local {
-                        panic!("lock guard overwritten while not dropped");
+                        self.untracked_guards.borrow_mut().entry(basic_block_data.terminator().source_info.span).or_insert("lock guard overwritten while not dropped");
+                        return guard_state.combine(GuardState::Undetermined);
                     }
 
                     // If the guard is passed into the function, this will be the local of the guard
@@ -482,13 +1852,15 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                         
                         match arg {
                             // lock guard is moved into the function and assumed for now to be dropped in that function, finish analysis
-                            Operand::Move(place) if place.local == current_local => {
+                            Operand::Move(place) if contains_guard(place, current_local, guard_field) => {
+                                guard_arg_local = Some(Local::from_u32(i as u32 + 1));
+                                break;
+                            },
+                            // guards can't be copied, this is a move which optimized mir turned into a copy
+                            Operand::Copy(place) if copies_guard(place, current_local, guard_field) => {
                                 guard_arg_local = Some(Local::from_u32(i as u32 + 1));
                                 break;
                             },
-                            // FIXME: I don't know if this is actually true, I think after drop elaboration
-                            // the compiler may turn moves into copies
-                            Operand::Copy(place) if place.local == current_local => panic!("lock guard cannot be copied"),
                             _ => continue,
                         }
                     }
@@ -496,15 +1868,67 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                     // FIXME: I think this could be a compiler intrisic
                     // currently this function will return None, and we will assume intrinsice drops argument
                     // but it might be better to hard code the case for compiler intrinsics and what they do
+                    let passes_guard_reference = args.iter().any(|arg| matches!(
+                        arg,
+                        Operand::Move(place) | Operand::Copy(place) if self.guard_references.contains(&(basic_block_id.def_id, place.local))
+                    ));
+
                     let fn_def_id = get_fn_def_id_from_terminator(&basic_block_data.terminator());
+                    if let Some(fn_def_id) = fn_def_id {
+                        self.record_blocking_call(basic_block_id.def_id, fn_def_id, basic_block_data.terminator().source_info.span);
+                    }
+
                     match (guard_arg_local, fn_def_id) {
-                        // if lock guard was passed into function, but we don't know which function, just assume it was dropped
+                        (Some(_arg), _) if self.is_guard_place(mir_body, destination, self.guards) => {
+                            // another guard of the lock is returned, so the guard was converted (such as by downgrading a write guard)
+                            // and the lock is still held
+                            current_local = destination.local;
+                            guard_field = None;
+                        },
+                        // if lock guard was passed into function, but we don't know which function or can't analyse it,
+                        // in pessimistic mode assume it is kept held, otherwise just assume it was dropped
+                        (Some(_arg), fn_def_id) if self.pessimistic && fn_def_id.map_or(true, |fn_def_id| optimized_mir_or_skip(self.tcx, self.skipped_bodies, fn_def_id).is_none()) => {
+                            // the guard can't be followed any more, so it is held for the rest of the function
+                            path.assumed = true;
+                            if let Some(target) = target {
+                                self.collect_reachable(basic_block_id.with_basic_block(*target), &[], &path);
+                            }
+                            return guard_state.combine(GuardState::Dropped);
+                        },
                         // FIXME: this might not be correct
                         (Some(_arg), None) => return guard_state.combine(GuardState::Dropped),
+                        (Some(_arg), Some(fn_def_id)) if self.is_drop_wrapper(fn_def_id) => {
+                            // guard is now owned by the returned drop wrapper, and will be dropped when it is dropped
+                            current_local = destination.local;
+                            guard_field = None;
+                        },
+                        (Some(_arg), Some(fn_def_id)) if self.is_guard_combinator(fn_def_id) => {
+                            // the guard is passed to a closure which can't be followed through the combinator,
+                            // so assume the closure returns it, but still look for locks in the closure
+                            self.collect_callbacks_in_args(mir_body, args, &path);
+                            path.assumed = true;
+                            current_local = destination.local;
+                            guard_field = None;
+                        },
                         (Some(arg), Some(fn_def_id)) => {
-                            match self.collect_inner(Bbid::fn_start(fn_def_id), arg, false) {
+                            // the guard is captured by a closure which is being called, so follow it into the closure body
+                            let fn_def_id = if arg == CLOSURE_ENV_ARG {
+                                self.called_closure(mir_body, fn_def_id, args).unwrap_or(fn_def_id)
+                            } else {
+                                fn_def_id
+                            };
+
+                            let call_span = basic_block_data.terminator().source_info.span;
+                            let guard_state_in_callee = self.in_callee(basic_block_id.def_id, fn_def_id, call_span, |collector| {
+                                collector.collect_inner(Bbid::fn_start(fn_def_id), arg, None, false, path.clone())
+                            });
+
+                            match guard_state_in_callee {
                                 // guard will now be in function return local
-                                GuardState::Returned => current_local = destination.local,
+                                GuardState::Returned => {
+                                    current_local = destination.local;
+                                    guard_field = None;
+                                },
                                 // guard dropped finish analysis
                                 GuardState::Dropped => return guard_state.combine(GuardState::Dropped),
                                 // function went into infinite loop, return
@@ -512,11 +1936,21 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                             }
                         },
                         (None, Some(fn_def_id)) => {
-                            self.collect_all_invocations(fn_def_id);
+                            let call_span = basic_block_data.terminator().source_info.span;
+                            self.in_callee(basic_block_id.def_id, fn_def_id, call_span, |collector| {
+                                collector.collect_all_invocations(fn_def_id, &path)
+                            });
+                            self.collect_callbacks_in_args(mir_body, args, &path);
+
+                            if passes_guard_reference {
+                                // the function could release the guard through the reference (such as with `Option::take`),
+                                // so it is no longer certain the guard is held
+                                path.assumed = true;
+                            }
                         },
                         // we don't know what function was called, can't eximine if it locked anything
                         // FIXME: this might not be correct
-                        (None, None) => (),
+                        (None, None) => path.assumed = true,
                     }
 
                     if let Some(target) = target {
@@ -530,6 +1964,8 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
                 TerminatorKind::Yield { .. } => todo!(),
                 // aparently this is like a return from generator?
                 TerminatorKind::GeneratorDrop => todo!(),
+                // false edges are only added for borrow checking (such as between match guards), they never run,
+                // so only the real target affects what happens to the guard
                 TerminatorKind::FalseEdge { real_target, .. } => basic_block = *real_target,
                 TerminatorKind::FalseUnwind { real_target, .. } => basic_block = *real_target,
                 // TODO: detect if inline asm operands is local we are using
@@ -545,14 +1981,64 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
         }
     }
 
+    /// Returns the closure which is called if `fn_def_id` is a method of `Fn`, `FnMut` or `FnOnce` with a closure passed by value as the receiver
+    ///
+    /// This is how a closure which captured a guard by moving it is called
+    fn called_closure(&self, mir_body: &Body<'tcx>, fn_def_id: DefId, args: &[Operand<'tcx>]) -> Option<DefId> {
+        let trait_def_id = self.tcx.trait_of_item(fn_def_id)?;
+        self.tcx.fn_trait_kind_from_def_id(trait_def_id)?;
+
+        match args.first()?.ty(&mir_body.local_decls, self.tcx).kind() {
+            TyKind::Closure(closure_def_id, _) => Some(*closure_def_id),
+            _ => None,
+        }
+    }
+
+    /// Collects lock invocations in the `Drop` impl of the type of `place`, which runs when `place` is dropped
+    fn collect_destructor(&mut self, mir_body: &Body<'tcx>, place: &Place<'tcx>, path: &PathInfo) {
+        let ty = place.ty(&mir_body.local_decls, self.tcx).ty;
+        let Some(adt_def) = ty.ty_adt_def() else {
+            return;
+        };
+
+        if let Some(destructor) = self.tcx.adt_destructor(adt_def.did()) {
+            self.collect_all_invocations(destructor.did, path);
+        }
+    }
+
+    fn is_drop_wrapper(&self, fn_def_id: DefId) -> bool {
+        let fn_path = with_no_trimmed_paths!(self.tcx.def_path_str(fn_def_id));
+        self.config.is_drop_wrapper(&fn_path)
+    }
+
+    /// Returns true if the function is an `Option` or `Result` combinator which passes the value in it to a closure,
+    /// and returns a value which is assumed to still contain it
+    fn is_guard_combinator(&self, fn_def_id: DefId) -> bool {
+        let Some(impl_def_id) = self.tcx.impl_of_method(fn_def_id) else {
+            return false;
+        };
+
+        let Some(adt_def) = self.tcx.type_of(impl_def_id).skip_binder().ty_adt_def() else {
+            return false;
+        };
+
+        let is_option_or_result = self.tcx.is_diagnostic_item(sym::Option, adt_def.did())
+            || self.tcx.is_diagnostic_item(sym::Result, adt_def.did());
+
+        is_option_or_result && GUARD_COMBINATORS.contains(&self.tcx.item_name(fn_def_id).as_str())
+    }
+
     // TODO: this data can probably be cached for entire program
-    fn collect_all_invocations(&mut self, fn_def_id: DefId) {
-        if !self.visited_functions.insert(fn_def_id) {
-            // we have already visited this function
-            return
+    fn collect_all_invocations(&mut self, fn_def_id: DefId, path: &PathInfo) {
+        if let Some(visited_path) = self.visited_functions.get(&fn_def_id) {
+            if visited_path.confidence() >= path.confidence() {
+                // we have already visited this function with a path which is at least as likely
+                return;
+            }
         }
+        self.visited_functions.insert(fn_def_id, path.clone());
 
-        let Some(mir_body) = self.tcx.try_optimized_mir(fn_def_id) else {
+        let Some(mir_body) = optimized_mir_or_skip(self.tcx, self.skipped_bodies, fn_def_id) else {
             return;
         };
 
@@ -564,13 +2050,212 @@ impl<'a, 'tcx> DependantClassCollector<'a, 'tcx> {
 
             if self.invocation_map.contains_key(&bbid) {
                 // this is a lock invocation, add it to dependant classes
-                self.dependant_classes.insert(bbid);
+                self.record_dependant(bbid, path);
             } else if let Some(called_fn_def_id) = get_fn_def_id_from_terminator(&mir_body.basic_blocks[basic_block].terminator()) {
                 // this is a regular function call, collect invocations in that function
-                self.collect_all_invocations(called_fn_def_id);
+                let call_span = mir_body.basic_blocks[basic_block].terminator().source_info.span;
+                self.record_blocking_call(fn_def_id, called_fn_def_id, call_span);
+                self.in_callee(fn_def_id, called_fn_def_id, call_span, |collector| {
+                    collector.collect_all_invocations(called_fn_def_id, path)
+                });
+
+                if let TerminatorKind::Call { args, .. } = &mir_body.basic_blocks[basic_block].terminator().kind {
+                    self.collect_callbacks_in_args(mir_body, args, path);
+                }
+            }
+        }
+    }
+
+    /// Collects lock invocations in closures and functions passed as arguments to a function call
+    ///
+    /// Higher order functions such as `Iterator::for_each` only call the callback through a generic parameter,
+    /// function pointer or trait object, so its body can't be reached by following calls from the function it is passed to
+    fn collect_callbacks_in_args(&mut self, mir_body: &Body<'tcx>, args: &[Operand<'tcx>], path: &PathInfo) {
+        for arg in args.iter() {
+            if let Some(callback_def_id) = self.resolve_callback(mir_body, arg, 0) {
+                self.collect_all_invocations(callback_def_id, path);
             }
         }
     }
+
+    /// Finds the init function of the lock `lock` refers to
+    ///
+    /// The init function is part of the lock's type if it is a closure, otherwise it was cast to a function pointer,
+    /// and it can only be found if the lock is a static, from the arguments passed to `constructor` in the static's initializer
+    fn resolve_init_fn(&self, mir_body: &Body<'tcx>, lock: &Operand<'tcx>, constructor: DefId) -> Option<DefId> {
+        let TyKind::Adt(_, generic_args) = peel_lock_wrappers(self.tcx, lock.ty(&mir_body.local_decls, self.tcx)).kind() else {
+            return None;
+        };
+
+        let init_fn_def_id = generic_args.types().find_map(|ty| match ty.kind() {
+            TyKind::Closure(def_id, _) | TyKind::FnDef(def_id, _) => Some(*def_id),
+            _ => None,
+        });
+        if init_fn_def_id.is_some() {
+            return init_fn_def_id;
+        }
+
+        let static_def_id = self.resolve_static(mir_body, lock, 0)?;
+        let initializer = optimized_mir_or_skip(self.tcx, self.skipped_bodies, static_def_id)?;
+
+        initializer.basic_blocks.iter()
+            .map(|block_data| block_data.terminator())
+            .filter(|terminator| get_fn_def_id_from_terminator(terminator) == Some(constructor))
+            .filter_map(|terminator| match &terminator.kind {
+                TerminatorKind::Call { args, .. } => Some(args),
+                _ => None,
+            })
+            .flat_map(|args| args.iter())
+            .find_map(|arg| self.resolve_callback(initializer, arg, 0))
+    }
+
+    /// Finds the static which `operand` is a reference to, following reborrows made earlier in the same function
+    fn resolve_static(&self, mir_body: &Body<'tcx>, operand: &Operand<'tcx>, depth: usize) -> Option<DefId> {
+        if let Operand::Constant(constant) = operand {
+            return constant.check_static_ptr(self.tcx);
+        }
+
+        let place = operand.place()?;
+        if depth >= MAX_CALLBACK_DEPTH || !place.projection.is_empty() {
+            return None;
+        }
+
+        match local_assignment(mir_body, place.local)? {
+            Rvalue::Use(operand) => self.resolve_static(mir_body, operand, depth + 1),
+            Rvalue::Ref(_, _, place) if matches!(place.projection[..], [ProjectionElem::Deref]) => {
+                self.resolve_static(mir_body, &Operand::Copy(place.local.into()), depth + 1)
+            },
+            _ => None,
+        }
+    }
+
+    /// Finds the closure or function which `operand` refers to,
+    /// following casts to function pointers and trait objects made earlier in the same function
+    fn resolve_callback(&self, mir_body: &Body<'tcx>, operand: &Operand<'tcx>, depth: usize) -> Option<DefId> {
+        match peel_lock_wrappers(self.tcx, operand.ty(&mir_body.local_decls, self.tcx)).kind() {
+            TyKind::Closure(def_id, _) | TyKind::FnDef(def_id, _) => return Some(*def_id),
+            _ => (),
+        }
+
+        let place = operand.place()?;
+        if depth >= MAX_CALLBACK_DEPTH || !place.projection.is_empty() {
+            return None;
+        }
+
+        match local_assignment(mir_body, place.local)? {
+            Rvalue::Use(operand) | Rvalue::Cast(_, operand, _) => self.resolve_callback(mir_body, operand, depth + 1),
+            Rvalue::Ref(_, _, place) => self.resolve_callback(mir_body, &Operand::Copy(*place), depth + 1),
+            _ => None,
+        }
+    }
+}
+
+/// Finds the value the local was created from
+fn local_assignment<'a, 'tcx>(mir_body: &'a Body<'tcx>, local: Local) -> Option<&'a Rvalue<'tcx>> {
+    mir_body.basic_blocks.iter()
+        .flat_map(|block_data| block_data.statements.iter())
+        .find_map(|statement| match &statement.kind {
+            StatementKind::Assign(assign_data) if assign_data.0.local == local && assign_data.0.projection.is_empty() => {
+                Some(&assign_data.1)
+            },
+            _ => None,
+        })
+}
+
+/// Local of a closure body which holds the closure's captured variables
+const CLOSURE_ENV_ARG: Local = Local::from_u32(1);
+
+/// Methods of `Option` and `Result` which pass the contained value to a closure and return the result
+///
+/// Other combinators either don't pass the guard to the closure or don't return what the closure returns
+const GUARD_COMBINATORS: &[&str] = &[
+    "map",
+    "and_then",
+];
+
+/// How many assignments are followed back to find the closure or function a callback refers to
+const MAX_CALLBACK_DEPTH: usize = 4;
+
+/// How many different guard locals a block is traversed with before it is no longer traversed
+const MAX_LOCALS_PER_BLOCK: usize = 8;
+
+/// Removes references and smart pointers (`Box`, `Arc` and `Rc`) from the given type
+///
+/// This is used to find the lock type when the lock is reached through one of these wrappers
+fn peel_lock_wrappers<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>) -> Ty<'tcx> {
+    let mut ty = ty.peel_refs();
+
+    loop {
+        let TyKind::Adt(adt_def, generic_args) = ty.kind() else {
+            return ty;
+        };
+
+        let is_wrapper = adt_def.is_box()
+            || tcx.is_diagnostic_item(sym::Arc, adt_def.did())
+            || tcx.is_diagnostic_item(sym::Rc, adt_def.did());
+
+        if !is_wrapper {
+            return ty;
+        }
+
+        ty = generic_args.type_at(0).peel_refs();
+    }
+}
+
+/// Checks if a basic block always ends in a panic or unreachable code
+fn block_diverges(mir_body: &Body, mut basic_block: BasicBlock) -> bool {
+    let mut visited = HashSet::new();
+
+    while visited.insert(basic_block) {
+        match &mir_body[basic_block].terminator().kind {
+            TerminatorKind::Goto { target } => basic_block = *target,
+            TerminatorKind::Unreachable
+                | TerminatorKind::UnwindResume
+                | TerminatorKind::UnwindTerminate(_)
+                | TerminatorKind::Call { target: None, .. } => return true,
+            _ => return false,
+        }
+    }
+
+    // infinite loop of gotos
+    true
+}
+
+/// Returns true if `basic_block` can run again after it finishes, because it is in the body of a loop
+///
+/// Cleanup blocks are ignored, since unwinding never goes back into a loop
+fn block_in_loop(mir_body: &Body, basic_block: BasicBlock) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = mir_body[basic_block].terminator().successors().collect::<Vec<_>>();
+
+    while let Some(block) = stack.pop() {
+        if block == basic_block {
+            return true;
+        }
+
+        if !visited.insert(block) || mir_body[block].is_cleanup {
+            continue;
+        }
+
+        stack.extend(mir_body[block].terminator().successors());
+    }
+
+    false
+}
+
+/// Gets the mir of `def_id`, recording why it was skipped if the mir can't be obtained
+fn optimized_mir_or_skip<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    skipped_bodies: &RefCell<FxIndexMap<DefId, MissingMir>>,
+    def_id: DefId,
+) -> Option<&'tcx Body<'tcx>> {
+    match tcx.optimized_mir_or_reason(def_id) {
+        Ok(body) => Some(body),
+        Err(reason) => {
+            skipped_bodies.borrow_mut().insert(def_id, reason);
+            None
+        },
+    }
 }
 
 fn get_fn_def_id_from_terminator(terminator: &Terminator) -> Option<DefId> {
@@ -593,48 +2278,153 @@ fn get_fn_def_id_from_terminator(terminator: &Terminator) -> Option<DefId> {
     Some(*def_id)
 }
 
+/// Returns true if `place` is the guard, or a value containing it
+///
+/// Fields next to the guard (such as `tuple.1` when the guard is in `tuple.0`) are not the guard
+fn contains_guard(place: &Place, current_local: Local, guard_field: Option<FieldIdx>) -> bool {
+    if place.local != current_local {
+        return false;
+    }
+
+    match (guard_field, first_field(place)) {
+        (Some(guard_field), Some(field)) => field == guard_field,
+        _ => true,
+    }
+}
+
+/// Returns true if copying `place` would copy the guard, which is really a move since guards can't be copied
+///
+/// Fields next to the guard can still be copied
+fn copies_guard(place: &Place, current_local: Local, guard_field: Option<FieldIdx>) -> bool {
+    if place.local != current_local {
+        return false;
+    }
+
+    match (guard_field, first_field(place)) {
+        (Some(guard_field), Some(field)) => field == guard_field,
+        _ => place.projection.is_empty(),
+    }
+}
+
+/// Returns the first field projected from the place's local, skipping downcasts to an enum variant
+fn first_field(place: &Place) -> Option<FieldIdx> {
+    place.projection.iter()
+        .take_while(|elem| matches!(elem, ProjectionElem::Field(..) | ProjectionElem::Downcast(..)))
+        .find_map(|elem| match elem {
+            ProjectionElem::Field(field, _) => Some(field),
+            _ => None,
+        })
+}
+
+/// Returns true if the statement assigns a new value to the whole guard local
+fn overwrites_guard(statement: &Statement, current_local: Local) -> bool {
+    let StatementKind::Assign(assign_data) = &statement.kind else {
+        return false;
+    };
+
+    let moves_guard = matches!(
+        &assign_data.1,
+        Rvalue::Use(Operand::Move(place)) if place.local == current_local
+    );
+
+    assign_data.0.local == current_local && assign_data.0.projection.is_empty() && !moves_guard
+}
+
 /// Tracks where the given local will be after executing the statement
 ///
-/// Used to track which local the lock guard is in
-/// This is currently a flawed implenentation which does not consider projections
-fn calculate_new_local_after_statement(statement: &Statement, current_local: Local) -> Local {
+/// Used to track which local the lock guard is in, and which field of that local if it was put in an aggregate
+/// Returns `None` if the guard's local is reused
+fn calculate_new_local_after_statement(
+    statement: &Statement,
+    current_local: Local,
+    guard_field: Option<FieldIdx>,
+) -> Option<(Local, Option<FieldIdx>)> {
     match &statement.kind {
         StatementKind::Assign(assign_data) => {
             let from_operand = match &assign_data.1 {
                 Rvalue::Use(operand) => operand,
-                // FIXME: handle this case correctly
-                // aggregute is used when constructing a struct or enum, so the mutex guard could be put in a struct
+                // a value containing the guard is coerced to a trait object, such as `Box<dyn Trait>`
+                Rvalue::Cast(CastKind::PointerCoercion(PointerCoercion::Unsize), operand, _) => operand,
+                // aggregate is used when constructing a struct or enum, so the guard is now in one of its fields
                 Rvalue::Aggregate(_, arguments) => {
-                    for arg in arguments.iter() {
+                    for (field, arg) in arguments.iter_enumerated() {
                         match arg {
-                            // FIXME: I don't know if this is actually true, I think after drop elaboration
-                            // the compiler may turn moves into copies
-                            Operand::Copy(place) if place.local == current_local => panic!("lock guard cannot be copied"),
-                            Operand::Move(place) if place.local == current_local => return assign_data.0.local,
+                            // guards can't be copied, this is a move which optimized mir turned into a copy
+                            Operand::Copy(place) if copies_guard(place, current_local, guard_field) => return Some((assign_data.0.local, Some(field))),
+                            Operand::Move(place) if contains_guard(place, current_local, guard_field) => return Some((assign_data.0.local, Some(field))),
                             _ => continue,
                         }
                     }
 
                     // none of the args to adt are the current local, so current local has not changed places
-                    return current_local;
+                    return Some((current_local, guard_field));
                 },
                 // the rest of rvalues for the most part won't be used on something like a lock guard
-                _ => return current_local,
+                _ => return Some((current_local, guard_field)),
             };
 
             match from_operand {
-                // FIXME: I don't know if this is actually true, I think after drop elaboration
-                // the compiler may turn moves into copies
-                Operand::Copy(place) if place.local == current_local => panic!("lock guard cannot be copied"),
-                Operand::Move(place) if place.local == current_local => assign_data.0.local,
-                _ => current_local,
+                // copy propagation turns the move of a temporary into a copy when the temporary is borrowed,
+                // such as when the guard is moved from the temporary unwrap returned it in into the user's binding,
+                // guards can't be copied so this is still a move
+                Operand::Copy(place) if copies_guard(place, current_local, guard_field) => Some((assign_data.0.local, moved_guard_field(place, guard_field))),
+                Operand::Move(place) if contains_guard(place, current_local, guard_field) => Some((assign_data.0.local, moved_guard_field(place, guard_field))),
+                _ => Some((current_local, guard_field)),
             }
         },
-        StatementKind::Deinit(place) if place.local == current_local => panic!("invalid deinit"),
-        // calling storage live on an already alive local is ub
-        StatementKind::StorageLive(local) if *local == current_local => panic!("invalid storage live"),
-        StatementKind::StorageDead(local) if *local == current_local => panic!("invalid storage dead"),
+        // optimized mir may reuse the local after the guard is gone in ways that aren't tracked
+        StatementKind::Deinit(place) if place.local == current_local => None,
+        StatementKind::StorageLive(local) if *local == current_local => None,
+        StatementKind::StorageDead(local) if *local == current_local => None,
         // any other statement assume it doesn't do anything
-        _ => current_local,
+        _ => Some((current_local, guard_field)),
+    }
+}
+
+/// Returns the field the guard is in after `place` is moved, which is the same field if the whole aggregate was moved
+fn moved_guard_field(place: &Place, guard_field: Option<FieldIdx>) -> Option<FieldIdx> {
+    if place.projection.is_empty() {
+        guard_field
+    } else {
+        None
+    }
+}
+
+/// Maps a span inside a macro expansion to the macro invocation in the user's code
+///
+/// Also returns a description of the outermost macro, or `None` if the span is not from a macro expansion.
+/// Desugarings such as `?` or `for` loops are not macros, so they are left as is.
+fn macro_callsite(span: Span) -> (Span, Option<String>) {
+    let outermost_macro = span.macro_backtrace()
+        .filter(|expn_data| matches!(expn_data.kind, ExpnKind::Macro(..)))
+        .last();
+
+    match outermost_macro {
+        Some(expn_data) => (span.source_callsite(), Some(expn_data.kind.descr())),
+        None => (span, None),
+    }
+}
+
+/// Removes the leading path from every path in a type name, so `std::sync::Mutex<crate::A>` becomes `Mutex<A>`
+fn strip_paths(name: &str) -> String {
+    let mut out = String::new();
+    let mut segment = String::new();
+    let mut chars = name.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '_' {
+            segment.push(c);
+        } else if c == ':' && chars.peek() == Some(&':') {
+            // this segment is part of a path, drop it
+            chars.next();
+            segment.clear();
+        } else {
+            out.push_str(&segment);
+            segment.clear();
+            out.push(c);
+        }
     }
+
+    out.push_str(&segment);
+    out
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// How often progress is printed
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Periodically prints how many items of a stage of analysis have been processed
+pub struct Progress {
+    enabled: bool,
+    /// Description of the items, such as `functions scanned`
+    what: &'static str,
+    done: usize,
+    total: usize,
+    last_print: Instant,
+}
+
+impl Progress {
+    pub fn new(enabled: bool, what: &'static str, total: usize) -> Self {
+        Progress {
+            enabled,
+            what,
+            done: 0,
+            total,
+            last_print: Instant::now(),
+        }
+    }
+
+    /// Marks one more item as processed, and prints the count if enough time has passed since it was last printed
+    pub fn step(&mut self) {
+        self.done += 1;
+
+        if self.enabled && self.last_print.elapsed() >= PROGRESS_INTERVAL {
+            self.print();
+            self.last_print = Instant::now();
+        }
+    }
+
+    /// Prints the final count
+    pub fn finish(&self) {
+        if self.enabled {
+            self.print();
+        }
+    }
+
+    fn print(&self) {
+        eprintln!("lockcheck: {}/{} {}", self.done, self.total, self.what);
+    }
+}
\ No newline at end of file
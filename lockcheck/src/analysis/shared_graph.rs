@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use rustc_middle::ty::{TyCtxt, Ty};
+use rustc_middle::ty::print::with_no_trimmed_paths;
+use rustc_span::Span;
+use rustc_span::def_id::LOCAL_CRATE;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+use super::pass::AnalysisPass;
+
+/// An edge in the lock dependancy graph which was found in another crate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedEdge {
+    /// Type of the lock which is held
+    pub parent: String,
+    /// Type of the lock which is locked while the parent is held
+    pub child: String,
+    pub crate_name: String,
+    /// Location of the child lock invocation
+    pub location: String,
+}
+
+/// Lock dependancy edges from all crates checked so far in the same run
+///
+/// Lockcheck runs once per crate, so each crate's edges are written to a shared directory (`--shared-graph-dir`),
+/// which lets crates checked later detect deadlocks spanning multiple crates.
+/// Cargo always checks a crate after its dependencies, so the edges of every crate it is linked with are there
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SharedGraph {
+    edges: Vec<SharedEdge>,
+}
+
+/// The edges of one crate, along with the key its graph is saved under
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CrateGraph {
+    pub crate_key: String,
+    pub graph: SharedGraph,
+}
+
+impl CrateGraph {
+    pub fn save(&self, dir: Option<&Path>) -> Result<()> {
+        self.graph.save(dir, &self.crate_key)
+    }
+}
+
+impl SharedGraph {
+    /// Loads edges saved by all other crates
+    pub fn load(dir: Option<&Path>, crate_key: &str) -> Result<Self> {
+        let mut graph = SharedGraph::default();
+
+        let Some(dir) = dir else {
+            return Ok(graph);
+        };
+
+        if !dir.exists() {
+            return Ok(graph);
+        }
+
+        // read_dir order depends on the file system, so sort to find the same cross crate paths every run
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+
+        for path in paths {
+            // skip graphs which are still being written
+            if path.extension().map_or(true, |extension| extension != "toml") {
+                continue;
+            }
+
+            if path.file_stem().is_some_and(|stem| stem == crate_key) {
+                continue;
+            }
+
+            let crate_graph: SharedGraph = toml::from_str(&std::fs::read_to_string(path)?)?;
+            graph.edges.extend(crate_graph.edges);
+        }
+
+        Ok(graph)
+    }
+
+    /// Creates a graph from the edges found by the analysis pass of the current crate
+    pub fn from_pass<'tcx>(tcx: TyCtxt<'tcx>, pass: &AnalysisPass<'tcx>) -> Self {
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+
+        let edges = pass.invocation_edges()
+            .map(|(parent, child)| SharedEdge {
+                parent: ty_name(parent.ty),
+                child: ty_name(child.ty),
+                crate_name: crate_name.clone(),
+                location: span_location(tcx, child.callsite),
+            })
+            .collect();
+
+        SharedGraph {
+            edges,
+        }
+    }
+
+    /// Saves the edges for the given crate so other crates can use them
+    pub fn save(&self, dir: Option<&Path>, crate_key: &str) -> Result<()> {
+        let Some(dir) = dir else {
+            return Ok(());
+        };
+
+        // crates may be checked in parallel, so the graph is renamed into place once it is fully written
+        std::fs::create_dir_all(dir)?;
+        let temp_path = dir.join(format!("{}.tmp", crate_key));
+        std::fs::write(&temp_path, toml::to_string(self)?)?;
+        std::fs::rename(temp_path, dir.join(format!("{}.toml", crate_key)))?;
+
+        Ok(())
+    }
+
+    /// Hashes the graphs saved by all other crates, so results which depend on them can be cached
+    pub fn hash_saved(dir: Option<&Path>, crate_key: &str, hasher: &mut impl Hasher) -> Result<()> {
+        let Some(dir) = dir else {
+            return Ok(());
+        };
+
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+
+        for path in paths {
+            if path.extension().map_or(true, |extension| extension != "toml") {
+                continue;
+            }
+
+            if path.file_stem().is_some_and(|stem| stem == crate_key) {
+                continue;
+            }
+
+            path.file_name().hash(hasher);
+            std::fs::read(path)?.hash(hasher);
+        }
+
+        Ok(())
+    }
+
+    /// Searches for a path from `from` to `to` which goes through at least one edge from another crate
+    /// 
+    /// `local_edges` are the edges found in the current crate, and paths using only those are ignored,
+    /// since they are already reported as regular deadlocks.
+    /// Returns the first edge from another crate on the path.
+    pub fn find_cross_crate_path<'a>(
+        &'a self,
+        from: &str,
+        to: &str,
+        local_edges: &[(String, String)],
+    ) -> Option<&'a SharedEdge> {
+        // each entry is the current type, and the first external edge used to reach it
+        let mut stack: Vec<(&str, Option<&SharedEdge>)> = vec![(from, None)];
+        let mut visited = HashSet::new();
+
+        while let Some((current, external_edge)) = stack.pop() {
+            if current == to && external_edge.is_some() {
+                return external_edge;
+            }
+
+            if !visited.insert((current, external_edge.is_some())) {
+                continue;
+            }
+
+            for (parent, child) in local_edges.iter() {
+                if parent == current {
+                    stack.push((child, external_edge));
+                }
+            }
+
+            for edge in self.edges.iter() {
+                if edge.parent == current {
+                    stack.push((&edge.child, external_edge.or(Some(edge))));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Identifies the current crate's graph in the shared directory
+///
+/// A package's library and binary have the same crate name, so the stable crate id is included to tell them apart
+pub fn crate_key(tcx: TyCtxt) -> String {
+    format!("{}-{:016x}", tcx.crate_name(LOCAL_CRATE), tcx.stable_crate_id(LOCAL_CRATE).as_u64())
+}
+
+/// Gets the full name of a type, which is the same in every crate
+pub fn ty_name(ty: Ty) -> String {
+    with_no_trimmed_paths!(ty.to_string())
+}
+
+pub fn span_location(tcx: TyCtxt, span: Span) -> String {
+    tcx.sess.source_map().span_to_diagnostic_string(span)
+}
@@ -0,0 +1,39 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use rustc_data_structures::fx::FxIndexSet;
+
+use super::pass::AnalysisPass;
+
+/// Summary of how much locking was found in a crate
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub lock_classes: usize,
+    pub invocations: usize,
+    /// Number of edges in the lock class dependancy map
+    pub edges: usize,
+}
+
+impl Stats {
+    pub fn from_pass(pass: &AnalysisPass) -> Self {
+        let lock_classes = pass.invocations()
+            .map(|invocation| invocation.class)
+            .collect::<HashSet<_>>()
+            .len();
+
+        Stats {
+            lock_classes,
+            invocations: pass.invocations().count(),
+            edges: pass.dependant_map().values().map(FxIndexSet::len).sum(),
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "lockcheck stats:")?;
+        writeln!(f, "  lock classes: {}", self.lock_classes)?;
+        writeln!(f, "  lock invocations: {}", self.invocations)?;
+        writeln!(f, "  dependancy edges: {}", self.edges)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+
+/// How findings are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Rustc style diagnostics pointing at the source code
+    #[default]
+    Human,
+    /// One line per finding, starting with `path:line:col`
+    Short,
+    /// A json array of every finding, printed to stdout once analysis finishes
+    Json,
+    /// A SARIF log of every finding, printed to stdout once analysis finishes, for code scanning tools
+    Sarif,
+}
+
+/// Which lock call of a deadlock the main diagnostic span points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PrimarySpan {
+    /// The lock call where the lock is first held
+    First,
+    /// The later lock call which deadlocks
+    #[default]
+    Second,
+}
+
+/// Command line arguments for lockcheck
+///
+/// Lockcheck's own flags come first, optionally followed by `--`,
+/// and all remaining arguments are passed on to rustc
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// Write a starter `lockcheck.toml` for the current package instead of running analysis
+    pub init: bool,
+    /// Load the config from this file instead of searching for `lockcheck.toml`
+    pub config: Option<PathBuf>,
+    /// Resolve the lock targets in the config and print whether each one resolved, without running analysis
+    pub check_config: bool,
+    /// Analyse a single source file as a library crate, using the builtin lock targets instead of a config file
+    pub file: Option<String>,
+    /// Keep running and rerun the analysis whenever a source file changes
+    pub watch: bool,
+    /// Only report deadlocks with at least this confidence percentage
+    pub min_confidence: u8,
+    /// Analyse the crate as a test harness, so code behind `#[cfg(test)]` is checked
+    pub tests: bool,
+    /// Reuse the findings of the last run if the crate's sources, its dependencies and the config have not changed
+    ///
+    /// Cached findings are printed without source snippets or notes, since rustc can't render them without analysing the crate
+    pub cache: bool,
+    /// Print a summary of the lock classes and dependancies found after analysis
+    pub stats: bool,
+    /// Periodically print how much of the crate has been analysed
+    pub progress: bool,
+    /// Print every place a lock is acquired after analysis
+    pub list_locks: bool,
+    /// Assume functions which can't be analysed keep a lock guard passed to them held,
+    /// this finds more deadlocks at the cost of more false positives
+    pub pessimistic: bool,
+    /// Note locks which are acquired on every iteration of a loop, which could be hoisted out of the loop
+    pub perf_hints: bool,
+    /// Note which entry points (`main`, tests and spawned threads) each deadlock is reachable from
+    pub entry_points: bool,
+    /// Note every body which was not analysed because its mir could not be obtained
+    pub skipped_bodies: bool,
+    /// Note every lock guard which stopped being followed because optimized mir reused its local
+    pub untracked_guards: bool,
+    /// Only report findings involving one of these files, paths are relative to the workspace root
+    pub changed_files: Option<Vec<String>>,
+    /// How findings are printed
+    pub format: OutputFormat,
+    /// Write the findings to this file in the chosen format instead of printing them
+    pub output: Option<PathBuf>,
+    /// Disable mir optimizations and inlining, so the analysed mir keeps the lock calls written in the source
+    pub no_mir_opt: bool,
+    /// Rustc whose sysroot is used for analysis, instead of the one in `RUSTC`
+    pub rustc: Option<PathBuf>,
+    /// Directory where lock dependancy edges are shared between crates, so deadlocks spanning crates are reported
+    pub shared_graph_dir: Option<PathBuf>,
+    /// Which lock call of a deadlock the main diagnostic span points to
+    pub primary: PrimarySpan,
+    /// Names of a parent and child lock class whose relationship should be explained
+    pub explain: Option<(String, String)>,
+    /// Arguments which are passed to rustc
+    pub rustc_args: Vec<String>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self> {
+        // first argument is the program name
+        let args = std::env::args().skip(1).collect::<Vec<_>>();
+        Self::parse_from(&args)
+    }
+
+    fn parse_from(args: &[String]) -> Result<Self> {
+        let mut out = Args::default();
+
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--init" => out.init = true,
+                "--config" => out.config = Some(flag_value(args, &mut i, "--config")?.into()),
+                arg if arg.starts_with("--config=") => out.config = Some(arg["--config=".len()..].into()),
+                "--file" => out.file = Some(flag_value(args, &mut i, "--file")?.to_owned()),
+                "--check-config" => out.check_config = true,
+                "--watch" => out.watch = true,
+                "--tests" => out.tests = true,
+                "--stats" => out.stats = true,
+                "--cache" => out.cache = true,
+                "--pessimistic" => out.pessimistic = true,
+                "--list-locks" => out.list_locks = true,
+                "--progress" => out.progress = true,
+                "--perf-hints" => out.perf_hints = true,
+                "--entry-points" => out.entry_points = true,
+                "--skipped-bodies" => out.skipped_bodies = true,
+                "--untracked-guards" => out.untracked_guards = true,
+                "--no-mir-opt" => out.no_mir_opt = true,
+                "--min-confidence" => out.min_confidence = parse_min_confidence(flag_value(args, &mut i, "--min-confidence")?)?,
+                "--changed-files" => out.changed_files = Some(parse_changed_files(flag_value(args, &mut i, "--changed-files")?)),
+                arg if arg.starts_with("--changed-files=") => out.changed_files = Some(parse_changed_files(&arg["--changed-files=".len()..])),
+                "--format" => out.format = parse_format(flag_value(args, &mut i, "--format")?)?,
+                arg if arg.starts_with("--format=") => out.format = parse_format(&arg["--format=".len()..])?,
+                "--output" => out.output = Some(flag_value(args, &mut i, "--output")?.into()),
+                arg if arg.starts_with("--output=") => out.output = Some(arg["--output=".len()..].into()),
+                "--primary" => out.primary = parse_primary(flag_value(args, &mut i, "--primary")?)?,
+                arg if arg.starts_with("--primary=") => out.primary = parse_primary(&arg["--primary=".len()..])?,
+                "--shared-graph-dir" => out.shared_graph_dir = Some(flag_value(args, &mut i, "--shared-graph-dir")?.into()),
+                arg if arg.starts_with("--shared-graph-dir=") => out.shared_graph_dir = Some(arg["--shared-graph-dir=".len()..].into()),
+                "--rustc" => out.rustc = Some(flag_value(args, &mut i, "--rustc")?.into()),
+                arg if arg.starts_with("--rustc=") => out.rustc = Some(arg["--rustc=".len()..].into()),
+                "--explain" => {
+                    let parent = flag_value(args, &mut i, "--explain")?.to_owned();
+                    let child = flag_value(args, &mut i, "--explain")?.to_owned();
+                    out.explain = Some((parent, child));
+                },
+                "--" => {
+                    i += 1;
+                    break;
+                },
+                // the first argument lockcheck does not know is the start of the rustc arguments
+                _ => break,
+            }
+
+            i += 1;
+        }
+
+        out.rustc_args = args[i..].to_vec();
+        if let Some(file) = &out.file {
+            // the file may not have a main function
+            out.rustc_args.push("--crate-type=lib".to_owned());
+            out.rustc_args.push(file.clone());
+        }
+
+        Ok(out)
+    }
+
+    /// Returns the arguments used to analyse one of the crate roots listed in the config
+    ///
+    /// Each crate's edges are written to `shared_graph_dir`, so the crates analysed after it report deadlocks spanning both
+    pub fn for_crate_root(&self, crate_root: &str, shared_graph_dir: &Path) -> Args {
+        let mut args = self.clone();
+        args.shared_graph_dir = Some(shared_graph_dir.to_owned());
+        // the crate may not have a main function, and crate roots named `lib.rs` or `main.rs` need a name to tell them apart
+        args.rustc_args = vec![
+            "--crate-type=lib".to_owned(),
+            format!("--crate-name={}", crate_root_name(crate_root)),
+            crate_root.to_owned(),
+        ];
+        args
+    }
+
+    /// Returns the crate root source file passed to rustc
+    pub fn input_file(&self) -> Option<&str> {
+        self.rustc_args.iter()
+            .map(String::as_str)
+            .find(|arg| arg.ends_with(".rs"))
+    }
+
+    /// Returns true if the findings are written as a report once analysis finishes, instead of printed as diagnostics
+    pub fn writes_report(&self) -> bool {
+        self.output.is_some() || matches!(self.format, OutputFormat::Json | OutputFormat::Sarif)
+    }
+}
+
+/// Gets the crate name of a crate root, which is the package directory for `src/lib.rs` and `src/main.rs`
+fn crate_root_name(crate_root: &str) -> String {
+    let path = std::fs::canonicalize(crate_root).unwrap_or_else(|_| PathBuf::from(crate_root));
+    let package_dir = path.parent()
+        .filter(|dir| dir.ends_with("src"))
+        .and_then(Path::parent);
+
+    let name = match (path.file_stem(), package_dir) {
+        (Some(stem), Some(package_dir)) if stem == "lib" || stem == "main" => package_dir.file_name(),
+        (stem, _) => stem,
+    };
+
+    name.map_or_else(|| "crate_root".to_owned(), |name| name.to_string_lossy().replace('-', "_"))
+}
+
+fn parse_min_confidence(min_confidence: &str) -> Result<u8> {
+    match min_confidence.parse() {
+        Ok(min_confidence) if min_confidence <= 100 => Ok(min_confidence),
+        _ => bail!("`--min-confidence` must be a percentage from 0 to 100, got `{}`", min_confidence),
+    }
+}
+
+fn parse_format(format: &str) -> Result<OutputFormat> {
+    match format {
+        "human" => Ok(OutputFormat::Human),
+        "short" => Ok(OutputFormat::Short),
+        "json" => Ok(OutputFormat::Json),
+        "sarif" => Ok(OutputFormat::Sarif),
+        _ => bail!("`--format` must be `human`, `short`, `json` or `sarif`, got `{}`", format),
+    }
+}
+
+/// Splits a comma separated list of files, such as the output of `git diff --name-only` joined with commas
+fn parse_changed_files(files: &str) -> Vec<String> {
+    files.split(',')
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(|file| file.strip_prefix("./").unwrap_or(file).replace('\\', "/"))
+        .collect()
+}
+
+fn parse_primary(primary: &str) -> Result<PrimarySpan> {
+    match primary {
+        "first" => Ok(PrimarySpan::First),
+        "second" => Ok(PrimarySpan::Second),
+        _ => bail!("`--primary` must be `first` or `second`, got `{}`", primary),
+    }
+}
+
+/// Gets the value following a flag which takes a value
+fn flag_value<'a>(args: &'a [String], index: &mut usize, flag: &str) -> Result<&'a str> {
+    *index += 1;
+    let Some(value) = args.get(*index) else {
+        bail!("expected value after `{}`", flag);
+    };
+
+    Ok(value)
+}
@@ -0,0 +1,206 @@
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+use crate::analysis::{self, CrateGraph, ErrorStatus, Finding, Level, SharedGraph};
+use crate::args::{Args, OutputFormat};
+use crate::config::Config;
+use crate::report;
+
+/// Findings from the last run on a crate, along with the fingerprint of the config and the files they were found with
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResult {
+    /// This is stored as a string because toml integers are signed
+    fingerprint: String,
+    /// Fingerprint of the graphs other crates saved to the shared graph directory, which the findings also depend on
+    shared_graph_fingerprint: String,
+    /// Every source file rustc loaded in the last run, the findings are only used if none of these have changed
+    sources: Vec<InputStamp>,
+    /// Metadata of every dependency in the last run
+    dependencies: Vec<InputStamp>,
+    findings: Vec<Finding>,
+    /// Edges found in the crate, which are saved to the shared graph directory again when the cached findings are used
+    crate_graph: CrateGraph,
+}
+
+impl CachedResult {
+    /// Returns true if none of the files the findings were found with have changed
+    fn inputs_unchanged(&self) -> bool {
+        self.sources.iter().all(|stamp| InputStamp::source(stamp.path.clone()).as_ref() == Some(stamp))
+            && self.dependencies.iter().all(|stamp| InputStamp::dependency(stamp.path.clone()).as_ref() == Some(stamp))
+    }
+}
+
+/// A file the findings depend on, and a stamp which changes when the file changes
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct InputStamp {
+    path: PathBuf,
+    stamp: String,
+}
+
+impl InputStamp {
+    /// Source files are stamped with a hash of their contents, so only real changes invalidate the cache
+    fn source(path: PathBuf) -> Option<Self> {
+        let mut hasher = StableHasher::default();
+        std::fs::read(&path).ok()?.hash(&mut hasher);
+
+        Some(InputStamp {
+            path,
+            stamp: format!("{:016x}", hasher.finish()),
+        })
+    }
+
+    /// Dependency metadata is stamped with its modification time, since it can be large
+    fn dependency(path: PathBuf) -> Option<Self> {
+        let modified = std::fs::metadata(&path).ok()?
+            .modified().ok()?
+            .duration_since(std::time::UNIX_EPOCH).ok()?;
+
+        Some(InputStamp {
+            path,
+            stamp: format!("{}.{:09}", modified.as_secs(), modified.subsec_nanos()),
+        })
+    }
+}
+
+/// Runs analysis, unless the crate has not changed since the last cached run, in which case the cached findings are printed
+pub fn run_cached(config: &Config, args: &Args) -> Result<ErrorStatus> {
+    let input_file = args.input_file()
+        .ok_or_else(|| anyhow!("no input filename given"))?;
+    let fingerprint = format!("{:016x}", fingerprint(config, args)?);
+    let cache_path = cache_path(input_file);
+
+    let cached_result = std::fs::read_to_string(&cache_path).ok()
+        .and_then(|data| toml::from_str::<CachedResult>(&data).ok())
+        .filter(|cached_result| cached_result.fingerprint == fingerprint)
+        .filter(CachedResult::inputs_unchanged);
+
+    if let Some(cached_result) = cached_result {
+        let shared_graph_fingerprint = shared_graph_fingerprint(args, &cached_result.crate_graph.crate_key)?;
+        if cached_result.shared_graph_fingerprint == shared_graph_fingerprint {
+            // crates checked after this one still need its edges to find deadlocks spanning crates
+            cached_result.crate_graph.save(args.shared_graph_dir.as_deref())?;
+
+            if args.writes_report() {
+                report::write_report(&cached_result.findings, args)?;
+                return Ok(cached_status(&cached_result.findings));
+            }
+
+            return Ok(print_cached_findings(&cached_result.findings, args.format));
+        }
+    }
+
+    let (status, findings, crate_graph, inputs) = analysis::run_with_findings(config, args)?;
+    let shared_graph_fingerprint = shared_graph_fingerprint(args, &crate_graph.crate_key)?;
+
+    // if a file can't be stamped, there is no way to tell when it changes, so nothing is cached
+    let sources = inputs.sources.into_iter().map(InputStamp::source).collect::<Option<Vec<_>>>();
+    let dependencies = inputs.dependencies.into_iter().map(InputStamp::dependency).collect::<Option<Vec<_>>>();
+    let (Some(sources), Some(dependencies)) = (sources, dependencies) else {
+        return Ok(status);
+    };
+
+    let cached_result = CachedResult {
+        fingerprint,
+        shared_graph_fingerprint,
+        sources,
+        dependencies,
+        findings,
+        crate_graph,
+    };
+    std::fs::create_dir_all(cache_path.parent().unwrap())?;
+    std::fs::write(&cache_path, toml::to_string(&cached_result)?)?;
+
+    Ok(status)
+}
+
+/// Prints the findings to stderr like analysis does, with `--format short` the lines are the same as a live run
+///
+/// Rustc diagnostics can't be rendered without the compiler session, so the human format only shows the first lines of each one,
+/// the message and location, without the source snippet and notes
+fn print_cached_findings(findings: &[Finding], format: OutputFormat) -> ErrorStatus {
+    for finding in findings {
+        match format {
+            OutputFormat::Short => eprintln!("{}", report::short_line(finding)),
+            _ => eprintln!("{}", report::human_header(finding)),
+        }
+    }
+
+    if !findings.is_empty() {
+        eprintln!("note: crate is unchanged, showing cached results");
+    }
+
+    cached_status(findings)
+}
+
+fn cached_status(findings: &[Finding]) -> ErrorStatus {
+    if findings.iter().any(|finding| finding.level == Level::Error) {
+        ErrorStatus::DeadlockDetected
+    } else {
+        ErrorStatus::Ok
+    }
+}
+
+/// Each crate root gets its own cache file in the temporary directory
+fn cache_path(input_file: &str) -> PathBuf {
+    let mut hasher = StableHasher::default();
+    std::fs::canonicalize(input_file)
+        .unwrap_or_else(|_| input_file.into())
+        .hash(&mut hasher);
+
+    std::env::temp_dir()
+        .join("lockcheck-cache")
+        .join(format!("{:016x}.toml", hasher.finish()))
+}
+
+/// Hashes everything other than the input files which affects the result of analysis,
+/// which is the lockcheck version, the config, and the arguments
+fn fingerprint(config: &Config, args: &Args) -> Result<u64> {
+    let mut hasher = StableHasher::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    serde_json::to_string(config)?.hash(&mut hasher);
+    args.rustc_args.hash(&mut hasher);
+    args.tests.hash(&mut hasher);
+    args.min_confidence.hash(&mut hasher);
+    args.pessimistic.hash(&mut hasher);
+    args.perf_hints.hash(&mut hasher);
+    args.no_mir_opt.hash(&mut hasher);
+    args.primary.hash(&mut hasher);
+    args.entry_points.hash(&mut hasher);
+    args.skipped_bodies.hash(&mut hasher);
+    args.untracked_guards.hash(&mut hasher);
+    args.changed_files.hash(&mut hasher);
+
+    Ok(hasher.finish())
+}
+
+fn shared_graph_fingerprint(args: &Args, crate_key: &str) -> Result<String> {
+    let mut hasher = StableHasher::default();
+    SharedGraph::hash_saved(args.shared_graph_dir.as_deref(), crate_key, &mut hasher)?;
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// 64 bit FNV-1a, unlike `DefaultHasher` its output is the same with every version of rust,
+/// so cached results are still used after updating the toolchain
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        StableHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
\ No newline at end of file
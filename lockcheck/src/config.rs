@@ -1,43 +1,405 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::{Result, anyhow, Context};
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+
+/// What the lock method of a lock returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockReturn {
+    /// The guard is returned directly, such as `parking_lot::Mutex::lock`
+    Guard,
+    /// The guard is returned in a `Result`, such as `std::sync::Mutex::lock` or `std::sync::Mutex::try_lock`
+    #[default]
+    Result,
+    /// The guard is returned in an `Option`, such as `parking_lot::Mutex::try_lock`
+    Option,
+    /// A future which resolves to the guard is returned, such as `tokio::sync::Mutex::lock`
+    Future,
+}
+
+/// Which deadlocks cause lockcheck to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailOn {
+    /// Only deadlocks which do not depend on branch conditions or unanalysed function calls,
+    /// other deadlocks are reported as warnings
+    Guaranteed,
+    /// Any deadlock
+    #[default]
+    Any,
+}
+
+/// How a deadlock involving a lock is reported, overriding `fail_on`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A pair of lock classes, identified by their name or lock type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockOrder {
+    /// Lock which is held first
+    pub held: String,
+    /// Lock which is locked while `held` is held
+    pub locked: String,
+}
+
+/// Guard types of a lock, either a single path or a list of paths
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Guards {
+    One(String),
+    /// Every guard type of the lock, starting with the one returned by the lock method,
+    /// such as both the write and read guards of a `RwLock`
+    Many(Vec<String>),
+}
+
+impl Guards {
+    pub fn paths(&self) -> &[String] {
+        match self {
+            Guards::One(guard) => std::slice::from_ref(guard),
+            Guards::Many(guards) => guards,
+        }
+    }
+}
+
+/// Crate roots analysed by lockcheck, either a single path or a list of paths
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CrateRoots {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl CrateRoots {
+    pub fn paths(&self) -> &[String] {
+        match self {
+            CrateRoots::One(crate_root) => std::slice::from_ref(crate_root),
+            CrateRoots::Many(crate_roots) => crate_roots,
+        }
+    }
+}
 
 /// Identifies a lock type which will be checked
 // TODO: don't require specifying lock method and constructor path
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LockCheckTarget {
     pub lock: String,
-    pub guard: String,
+    /// Guard returned by the lock method, or a list of guards starting with the one returned by the lock method
+    ///
+    /// This is not set for locks which are only held while a closure runs
+    pub guard: Option<Guards>,
     /// Path to lock constructor
     pub constructor: String,
+    /// Arguments passed to the constructor to create a lock of `u8`, defaults to `0`
+    ///
+    /// For example this can be empty for a constructor which takes no arguments
+    #[serde(default = "default_constructor_args")]
+    pub constructor_args: String,
     /// Path to lock method
     pub lock_method: String,
+    /// What the lock method returns, defaults to a `Result` containing the guard
+    #[serde(default)]
+    pub returns: LockReturn,
+    /// Name shown in diagnostics instead of the lock's path, followed by the guarded type (such as `ValueLock<A>`)
+    pub name: Option<String>,
+    /// Set if the lock method takes the lock by value (`self`) instead of by reference (`&self`)
+    #[serde(default)]
+    pub by_value: bool,
+    /// Set if the lock is held only while a closure passed to the lock method runs (such as `OnceLock::get_or_init`)
+    #[serde(default)]
+    pub held_during_closure: bool,
+    /// Set if the lock is held only while the init function given to the constructor runs (such as `LazyLock::force`)
+    ///
+    /// `constructor_args` must pass an init function, such as `|| 0`
+    #[serde(default)]
+    pub held_during_init: bool,
+    /// Set if the lock method only takes shared access (such as `RefCell::borrow`),
+    /// so it does not conflict with other shared accesses of the same lock
+    #[serde(default)]
+    pub shared: bool,
+    /// Set if acquiring the lock while it is held panics instead of blocking (such as `RefCell`)
+    #[serde(default)]
+    pub panics: bool,
+    /// Set if the lock method is a trait method, so it is recognised when called on any implementor of the trait,
+    /// including generic types, `lock` and `constructor` only need to name one implementor
+    #[serde(default)]
+    pub trait_method: bool,
+    /// Index of the lock's generic type parameter which is the guarded value, this is used to identify lock classes
+    ///
+    /// Defaults to the first type parameter, the other type parameters must be inferable from the constructor
+    #[serde(default)]
+    pub value_index: usize,
+    /// How deadlocks involving this lock are reported, instead of using `fail_on`
+    ///
+    /// If the locks in a deadlock have different severities, the more severe one is used,
+    /// a lock without a severity does not change how a deadlock with a lock that has one is reported
+    pub severity: Option<Severity>,
+    /// Position of this lock in the lock hierarchy, locks must be acquired in increasing rank order
+    ///
+    /// Locking a lock while a lock with a higher rank is held is an error, even if the locks never form a cycle
+    pub rank: Option<u32>,
+    /// Set to false if the lock method never waits for the lock (such as `try_lock`),
+    /// so acquiring it while another lock is held is never a deadlock, defaults to true
+    #[serde(default = "default_blocking")]
+    pub blocking: bool,
+}
+
+fn default_blocking() -> bool {
+    true
+}
+
+fn default_constructor_args() -> String {
+    "0".to_owned()
 }
 
-#[derive(Debug, Deserialize)]
+fn default_erase_regions() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub locks: Vec<LockCheckTarget>,
+    /// Paths to functions which take ownership of a value and drop it once their return value is dropped
+    ///
+    /// For example `scopeguard::guard`, which is used to drop lock guards at the end of a scope
+    #[serde(default)]
+    pub drop_wrappers: Vec<String>,
+    /// Paths to functions which may block, a warning is emitted if a lock is held while one is called
+    ///
+    /// For example `std::sync::mpsc::Receiver::recv`
+    #[serde(default)]
+    pub blocking_functions: Vec<String>,
+    /// If true, functions in `std` which sleep or wait on another thread or process,
+    /// such as `std::thread::sleep` and `std::process::Child::wait`, are also blocking functions
+    #[serde(default)]
+    pub std_blocking_functions: bool,
+    /// Which deadlocks cause lockcheck to exit with an error
+    #[serde(default)]
+    pub fail_on: FailOn,
+    /// Orders of locks which are errors even if they do not form a cycle
+    #[serde(default)]
+    pub forbidden_orders: Vec<LockOrder>,
+    /// Orders of locks which are known to be safe, cycles between the two locks are not reported
+    ///
+    /// This is used when the opposite order is prevented at runtime in a way lockcheck can't see
+    #[serde(default)]
+    pub safe_pairs: Vec<LockOrder>,
+    /// Paths to guarded types whose locks are never reported, such as a metrics counter which is only locked briefly
+    ///
+    /// Locks of these types are still listed, but they never form edges with other locks
+    #[serde(default)]
+    pub ignore_types: Vec<String>,
+    /// If true, locks guarding types which only differ in lifetimes (such as `Mutex<&'a T>` and `Mutex<&'static T>`)
+    /// are the same lock class, defaults to true
+    #[serde(default = "default_erase_regions")]
+    pub erase_regions: bool,
+    /// If true, only code in the crate being checked is reported
+    ///
+    /// Dependencies are still followed to find which locks are held,
+    /// and blocking calls and await points in them are reported at the call into the dependency from the crate
+    #[serde(default)]
+    pub local_only: bool,
+    /// If true, lock calls in functions from dependencies which are called by the crate are also analysed,
+    /// so deadlocks where a dependency locks a lock the crate holds are reported
+    ///
+    /// Only functions whose mir is in the dependency's metadata (generic and `#[inline]` functions) can be analysed
+    #[serde(default)]
+    pub analyze_deps: bool,
+    /// Mir optimization level used for analysis, from 0 to 4
+    ///
+    /// Lower levels keep mir closer to the source, so diagnostics point at the lock calls as they are written.
+    /// `--no-mir-opt` overrides this
+    pub mir_opt_level: Option<usize>,
+    /// If false, calls are never inlined into the functions calling them,
+    /// so locks acquired in a helper function are reported in the helper instead of where it is called.
+    /// `--no-mir-opt` overrides this
+    pub inline_mir: Option<bool>,
+    /// Crate root source files analysed when lockcheck is run without rustc arguments, relative to the current directory
+    ///
+    /// With a list, each crate is analysed in turn in the same run, so deadlocks where the crates lock the same locks
+    /// in different orders are reported
+    pub crate_root: Option<CrateRoots>,
+    /// Edition used to analyse the crate when rustc is not passed an edition
+    ///
+    /// If this is not set, the edition is taken from `Cargo.toml`
+    pub edition: Option<String>,
+}
+
+impl Config {
+    /// Returns true if the function with the given path is a configured drop wrapper
+    pub fn is_drop_wrapper(&self, fn_path: &str) -> bool {
+        self.drop_wrappers.iter()
+            .any(|wrapper| path_matches(wrapper, fn_path))
+    }
+
+    /// Returns true if the function with the given path is a configured blocking function
+    pub fn is_blocking_function(&self, fn_path: &str) -> bool {
+        let std_blocking_functions = if self.std_blocking_functions {
+            STD_BLOCKING_FUNCTIONS
+        } else {
+            &[]
+        };
+
+        self.blocking_functions.iter()
+            .map(String::as_str)
+            .chain(std_blocking_functions.iter().copied())
+            .any(|function| path_matches(function, fn_path))
+    }
+}
+
+/// Functions in `std` which may block for a long time, used with `std_blocking_functions`
+const STD_BLOCKING_FUNCTIONS: &[&str] = &[
+    "std::thread::sleep",
+    "std::thread::park",
+    "std::thread::park_timeout",
+    "std::thread::JoinHandle::join",
+    "std::process::Child::wait",
+    "std::process::Child::wait_with_output",
+    "std::process::Command::output",
+    "std::process::Command::status",
+    "std::sync::Barrier::wait",
+    "std::sync::mpsc::Receiver::recv",
+    "std::sync::mpsc::Receiver::recv_timeout",
+    "std::sync::mpsc::SyncSender::send",
+];
+
+/// Returns a config which checks the locks in `std`, used when analysing a single file outside of a package
+pub fn builtin_config() -> Config {
+    let mut config: Config = toml::from_str(crate::init::STD_TARGETS)
+        .expect("builtin lock targets are invalid");
+    config.edition = Some("2021".to_owned());
+    config
+}
+
+/// Checks if a path from the config refers to the given rustc def path
+///
+/// Rustc does not prefix items in the local crate with `crate::`, so it is ignored.
+/// Generic arguments in the def path of methods (such as `Receiver::<T>::recv`) are also ignored.
+pub(crate) fn path_matches(config_path: &str, def_path: &str) -> bool {
+    config_path.strip_prefix("crate::").unwrap_or(config_path) == strip_generic_args(def_path)
+}
+
+/// Removes all `::<...>` generic argument segments from a path
+fn strip_generic_args(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut depth = 0usize;
+
+    for c in path.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => out.push(c),
+            _ => (),
+        }
+    }
+
+    out.replace("::::", "::")
+}
+
+/// Returns the root of the cargo workspace the current directory is in
+///
+/// This is the outermost directory whose `Cargo.toml` has a `[workspace]` table, or the nearest package if there is no workspace
+pub(crate) fn workspace_root() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    let mut packages = current_dir.ancestors()
+        .filter(|dir| dir.join("Cargo.toml").exists());
+
+    let nearest_package = packages.next()?;
+    let workspace = std::iter::once(nearest_package)
+        .chain(packages)
+        .filter(|dir| read_cargo_toml(&dir.join("Cargo.toml")).is_ok_and(|cargo_toml| cargo_toml.contains_key("workspace")))
+        .last();
+
+    Some(workspace.unwrap_or(nearest_package).to_owned())
+}
+
+pub(crate) fn read_cargo_toml(path: &Path) -> Result<toml::Table> {
+    toml::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("invalid format of `{}`", path.display()))
+}
+
+/// Reads the package edition from a parsed `Cargo.toml` file
+fn cargo_toml_edition(cargo_toml: &toml::Table) -> Option<String> {
+    cargo_toml.get("package")
+        .and_then(|package| package.get("edition"))
+        .and_then(|edition| edition.as_str())
+        .map(str::to_owned)
+}
+
+/// Reads config from the `[package.metadata.lockcheck]` table of a parsed `Cargo.toml` file
+fn cargo_toml_config(cargo_toml: &toml::Table) -> Result<Option<Config>> {
+    let Some(lockcheck_table) = cargo_toml.get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("lockcheck")) else {
+        return Ok(None);
+    };
+
+    let config = lockcheck_table.clone()
+        .try_into()
+        .with_context(|| "invalid format of `[package.metadata.lockcheck]` in `Cargo.toml`")?;
+
+    Ok(Some(config))
 }
 
 /// Attempts to load config from the `lockcheck.toml` config file
 /// 
-/// This will search all parent directories that contain a `Cargo.toml` file, and try to load the `lockcheck.toml` from the same directory
+/// This will search all parent directories that contain a `Cargo.toml` file, and try to load the `lockcheck.toml` from the same directory.
+/// If there is no `lockcheck.toml`, the `[package.metadata.lockcheck]` table of the `Cargo.toml` is used instead.
 pub fn load_config() -> Result<Config> {
     let current_dir = std::env::current_dir()?;
 
     for dir in current_dir.ancestors() {
-        if dir.join("Cargo.toml").exists() {
-            let lockcheck_config_path = dir.join("lockcheck.toml");
-            if !lockcheck_config_path.exists() {
-                continue;
-            }
+        let cargo_toml_path = dir.join("Cargo.toml");
+        if !cargo_toml_path.exists() {
+            continue;
+        }
+
+        let cargo_toml = read_cargo_toml(&cargo_toml_path)?;
 
+        let lockcheck_config_path = dir.join("lockcheck.toml");
+        let mut config: Config = if lockcheck_config_path.exists() {
             let config_data = std::fs::read_to_string(lockcheck_config_path)?;
-            let config: Config = toml::from_str(&config_data)
-                .with_context(|| "invalid format of lockecheck config file")?;
+            toml::from_str(&config_data)
+                .with_context(|| "invalid format of lockecheck config file")?
+        } else if let Some(config) = cargo_toml_config(&cargo_toml)? {
+            config
+        } else {
+            continue;
+        };
+
+        if config.edition.is_none() {
+            config.edition = cargo_toml_edition(&cargo_toml);
+        }
+
+        return Ok(config);
+    }
+
+    Err(anyhow!("Could not find `lockcheck.toml` config file or `[package.metadata.lockcheck]` in `Cargo.toml`"))
+}
+
+/// Loads config from the given file instead of searching for `lockcheck.toml`
+///
+/// If the file doesn't set an edition, it is taken from the nearest `Cargo.toml` to the current directory
+pub fn load_config_file(path: &Path) -> Result<Config> {
+    let config_data = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read config file `{}`", path.display()))?;
+    let mut config: Config = toml::from_str(&config_data)
+        .with_context(|| format!("invalid format of lockcheck config file `{}`", path.display()))?;
+
+    if config.edition.is_none() {
+        let current_dir = std::env::current_dir()?;
+        let cargo_toml_path = current_dir.ancestors()
+            .map(|dir| dir.join("Cargo.toml"))
+            .find(|cargo_toml_path| cargo_toml_path.exists());
 
-            return Ok(config);
+        if let Some(cargo_toml_path) = cargo_toml_path {
+            config.edition = cargo_toml_edition(&read_cargo_toml(&cargo_toml_path)?);
         }
     }
 
-    Err(anyhow!("Could not find `lockcheck.toml` config file"))
+    Ok(config)
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow, bail, Context};
+
+use crate::config::{self, Config};
+
+/// Lock targets for the locks in `std`
+pub(crate) const STD_TARGETS: &str = r#"
+[[locks]]
+lock = "std::sync::Mutex"
+guard = "std::sync::MutexGuard"
+constructor = "std::sync::Mutex::new"
+lock_method = "std::sync::Mutex::lock"
+
+[[locks]]
+lock = "std::sync::RwLock"
+guard = "std::sync::RwLockReadGuard"
+constructor = "std::sync::RwLock::new"
+lock_method = "std::sync::RwLock::read"
+shared = true
+
+[[locks]]
+lock = "std::sync::RwLock"
+guard = "std::sync::RwLockWriteGuard"
+constructor = "std::sync::RwLock::new"
+lock_method = "std::sync::RwLock::write"
+"#;
+
+/// Lock targets for the locks in `parking_lot`, only added if the package depends on it
+const PARKING_LOT_TARGETS: &str = r#"
+[[locks]]
+lock = "parking_lot::Mutex"
+guard = "parking_lot::MutexGuard"
+constructor = "parking_lot::Mutex::new"
+lock_method = "parking_lot::Mutex::lock"
+returns = "guard"
+
+[[locks]]
+lock = "parking_lot::RwLock"
+guard = "parking_lot::RwLockReadGuard"
+constructor = "parking_lot::RwLock::new"
+lock_method = "parking_lot::RwLock::read"
+returns = "guard"
+shared = true
+
+[[locks]]
+lock = "parking_lot::RwLock"
+guard = "parking_lot::RwLockWriteGuard"
+constructor = "parking_lot::RwLock::new"
+lock_method = "parking_lot::RwLock::write"
+returns = "guard"
+"#;
+
+/// Writes a starter `lockcheck.toml` next to the `Cargo.toml` of the package in the current directory
+///
+/// Returns the path of the written config
+pub fn init() -> Result<PathBuf> {
+    let current_dir = std::env::current_dir()?;
+    let package_dir = current_dir.ancestors()
+        .find(|dir| dir.join("Cargo.toml").exists())
+        .ok_or_else(|| anyhow!("could not find `Cargo.toml` in `{}` or any parent directory", current_dir.display()))?;
+
+    let config_path = package_dir.join("lockcheck.toml");
+    if config_path.exists() {
+        bail!("`{}` already exists", config_path.display());
+    }
+
+    let cargo_toml = config::read_cargo_toml(&package_dir.join("Cargo.toml"))?;
+    let config_data = starter_config(package_dir, &cargo_toml)?;
+
+    // make sure the written config is one lockcheck can load
+    toml::from_str::<Config>(&config_data)
+        .with_context(|| "generated config is invalid")?;
+
+    std::fs::write(&config_path, config_data)?;
+
+    Ok(config_path)
+}
+
+fn starter_config(package_dir: &Path, cargo_toml: &toml::Table) -> Result<String> {
+    let package_name = cargo_toml.get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(|name| name.as_str())
+        .unwrap_or("package");
+    let crate_root = ["src/lib.rs", "src/main.rs"].into_iter()
+        .find(|crate_root| package_dir.join(crate_root).exists());
+    let uses_parking_lot = cargo_toml.get("dependencies")
+        .and_then(|dependencies| dependencies.get("parking_lot"))
+        .is_some();
+
+    let mut out = String::new();
+    writeln!(out, "# lockcheck config for `{}`", package_name)?;
+    writeln!(out, "#")?;
+    writeln!(out, "# each `[[locks]]` table is a lock method which is checked, add tables for your own lock types")?;
+    if let Some(crate_root) = crate_root {
+        writeln!(out)?;
+        writeln!(out, "crate_root = \"{}\"", crate_root)?;
+    }
+
+    out.push_str(STD_TARGETS);
+    if uses_parking_lot {
+        out.push_str(PARKING_LOT_TARGETS);
+    }
+
+    Ok(out)
+}
\ No newline at end of file
@@ -0,0 +1,26 @@
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+extern crate rustc_interface;
+extern crate rustc_ast;
+extern crate rustc_hir;
+extern crate rustc_middle;
+extern crate rustc_session;
+extern crate rustc_hash;
+extern crate rustc_data_structures;
+extern crate rustc_span;
+extern crate rustc_errors;
+extern crate rustc_error_codes;
+extern crate rustc_error_messages;
+extern crate rustc_index;
+extern crate rustc_target;
+
+pub mod analysis;
+pub mod args;
+pub mod cache;
+pub mod config;
+pub mod init;
+pub mod report;
+mod rustc_config;
+mod tyctxt_ext;
+pub mod watch;
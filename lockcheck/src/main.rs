@@ -1,29 +1,54 @@
 #![feature(rustc_private)]
 
+// the rustc driver must be linked into the binary for the compiler crates used by lockcheck
 extern crate rustc_driver;
-extern crate rustc_interface;
-extern crate rustc_ast;
-extern crate rustc_hir;
-extern crate rustc_middle;
-extern crate rustc_session;
-extern crate rustc_hash;
-extern crate rustc_span;
-extern crate rustc_errors;
-extern crate rustc_error_codes;
-extern crate rustc_error_messages;
-extern crate rustc_index;
-
-mod analysis;
-mod config;
-mod rustc_config;
-mod tyctxt_ext;
 
 use anyhow::Result;
 
+use lockcheck::{analysis, cache, config, init, watch};
+use lockcheck::args::Args;
+use lockcheck::analysis::{ErrorStatus, Stats};
+use lockcheck::config::{Config, CrateRoots};
+
 fn run() -> Result<()> {
-    let config = config::load_config()?;
+    let args = Args::parse()?;
+
+    // the config doesn't exist yet, so this has to happen before it is loaded
+    if args.init {
+        let config_path = init::init()?;
+        println!("created `{}`", config_path.display());
+        return Ok(());
+    }
+
+    let config = if let Some(config_path) = &args.config {
+        config::load_config_file(config_path)?
+    } else if args.file.is_some() {
+        config::builtin_config()
+    } else {
+        config::load_config()?
+    };
+
+    if args.check_config {
+        if !analysis::check_config(&config, &args)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.watch {
+        return watch::watch(&config, &args);
+    }
+
+    let crate_roots = config.crate_root.as_ref()
+        .filter(|_| args.rustc_args.is_empty())
+        .map(CrateRoots::paths)
+        .unwrap_or_default();
+    let status = if crate_roots.is_empty() {
+        check_crate(&config, &args)?
+    } else {
+        check_crate_roots(&config, &args, crate_roots)?
+    };
 
-    let status = analysis::run(&config)?;
     if status.error_emitted() {
         // cargo panics if we emit an error but don't exit with non zero error code
         std::process::exit(1);
@@ -32,6 +57,51 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+fn check_crate(config: &Config, args: &Args) -> Result<ErrorStatus> {
+    // explain, stats and the lock list need the analysis pass, so they can't use cached results
+    if args.cache && args.explain.is_none() && !args.stats && !args.list_locks {
+        return cache::run_cached(config, args);
+    }
+
+    let (status, (explanation, stats, lock_list)) = analysis::run_with_pass(config, args, |pass| {
+        let explanation = args.explain.as_ref()
+            .map(|(parent, child)| analysis::explain(pass, parent, child));
+        let stats = args.stats.then(|| Stats::from_pass(pass));
+        let lock_list = args.list_locks.then(|| analysis::list_locks(pass));
+
+        (explanation, stats, lock_list)
+    })?;
+
+    if let Some(lock_list) = lock_list {
+        print!("{}", lock_list);
+    }
+    if let Some(explanation) = explanation {
+        print!("{}", explanation);
+    }
+    if let Some(stats) = stats {
+        print!("{}", stats);
+    }
+
+    Ok(status)
+}
+
+/// Checks each crate root from the config in turn, sharing lock dependancy edges between them
+fn check_crate_roots(config: &Config, args: &Args, crate_roots: &[String]) -> Result<ErrorStatus> {
+    let shared_graph_dir = args.shared_graph_dir.clone()
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("lockcheck-{}", std::process::id())));
+
+    let mut status = ErrorStatus::Ok;
+    for crate_root in crate_roots {
+        status = status | check_crate(config, &args.for_crate_root(crate_root, &shared_graph_dir))?;
+    }
+
+    if args.shared_graph_dir.is_none() && shared_graph_dir.exists() {
+        std::fs::remove_dir_all(&shared_graph_dir)?;
+    }
+
+    Ok(status)
+}
+
 fn main() {
     if let Err(err) = run() {
         println!("{:?}", err);
@@ -0,0 +1,127 @@
+use std::fmt::Write;
+
+use anyhow::{Result, Context};
+use serde_json::{Value, json};
+
+use crate::analysis::{Finding, Level, SourceLocation};
+use crate::args::{Args, OutputFormat};
+
+/// Writes the findings in the format chosen with `--format`, to the file given with `--output` or to stdout
+pub fn write_report(findings: &[Finding], args: &Args) -> Result<()> {
+    let report = match args.format {
+        OutputFormat::Json => serde_json::to_string_pretty(findings)?,
+        OutputFormat::Sarif => serde_json::to_string_pretty(&sarif_log(findings))?,
+        OutputFormat::Short => findings.iter().fold(String::new(), |mut report, finding| {
+            let _ = writeln!(report, "{}", short_line(finding));
+            report
+        }),
+        // rustc can't render diagnostics after the session ends, so the human format lists each finding with its location
+        OutputFormat::Human => findings.iter().fold(String::new(), |mut report, finding| {
+            let _ = writeln!(report, "{}", human_header(finding));
+            report
+        }),
+    };
+
+    match &args.output {
+        Some(path) => std::fs::write(path, report)
+            .with_context(|| format!("could not write report to `{}`", path.display())),
+        None => {
+            print!("{}", report);
+            Ok(())
+        },
+    }
+}
+
+/// Formats a finding in the form `path:line:col: level[code]: message (summary)`
+///
+/// This is also how findings are printed with `--format short`
+pub fn short_line(finding: &Finding) -> String {
+    let mut line = match &finding.source {
+        Some(source) => format!("{}:{}:{}: ", source.file, source.line, source.column),
+        None => String::new(),
+    };
+
+    let _ = write!(line, "{}[{}]: {}", level_name(finding.level), finding.code, finding.message);
+    if let Some(summary) = &finding.summary {
+        let _ = write!(line, " ({})", summary);
+    }
+
+    line
+}
+
+/// Formats the first lines of a finding's rustc diagnostic, in the form `level[code]: message` followed by the location
+pub fn human_header(finding: &Finding) -> String {
+    format!("{}[{}]: {}\n  --> {}", level_name(finding.level), finding.code, finding.message, finding.location)
+}
+
+/// Converts the findings to a SARIF 2.1.0 log, with one rule for each diagnostic code
+///
+/// The locks of a deadlock are related locations of its result
+fn sarif_log(findings: &[Finding]) -> Value {
+    let mut codes = findings.iter()
+        .map(|finding| finding.code.as_str())
+        .collect::<Vec<_>>();
+    codes.sort();
+    codes.dedup();
+
+    let rules = codes.iter()
+        .map(|code| json!({ "id": code }))
+        .collect::<Vec<_>>();
+
+    let results = findings.iter().map(|finding| {
+        let locations = finding.source.iter()
+            .map(|source| sarif_location(source, None))
+            .collect::<Vec<_>>();
+        let related_locations = finding.deadlock.iter()
+            .flat_map(|deadlock| [
+                sarif_location(&deadlock.held.source, Some(format!("`{}` is locked first", deadlock.held.name))),
+                sarif_location(&deadlock.locked.source, Some(format!("`{}` is locked while it is held", deadlock.locked.name))),
+            ])
+            .collect::<Vec<_>>();
+
+        json!({
+            "ruleId": finding.code,
+            "level": level_name(finding.level),
+            "message": { "text": finding.message },
+            "locations": locations,
+            "relatedLocations": related_locations,
+        })
+    }).collect::<Vec<_>>();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "lockcheck",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn sarif_location(source: &SourceLocation, message: Option<String>) -> Value {
+    let mut location = json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": source.file },
+            "region": { "startLine": source.line, "startColumn": source.column },
+        },
+    });
+    if let Some(message) = message {
+        location["message"] = json!({ "text": message });
+    }
+
+    location
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Note => "note",
+    }
+}
\ No newline at end of file
@@ -1,27 +1,124 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
 use rustc_interface::{interface, Config};
 use rustc_session::{EarlyErrorHandler, config::{self, ErrorOutputType}};
 use rustc_driver::handle_options;
 use rustc_driver::args::arg_expand_all;
 use rustc_span::{FileName, RealFileName};
+use rustc_span::source_map::{FileLoader, RealFileLoader};
+use rustc_data_structures::sync::Lrc;
 use rustc_errors::registry::Registry;
-use anyhow::{Result, bail};
+use anyhow::{Result, Context, anyhow, bail};
 
 use crate::analysis::generate_lock_filler;
+use crate::args::Args;
 use super::config::Config as LockCheckConfig;
 
-pub fn get_rustc_config(lock_check_config: &LockCheckConfig) -> Result<Config> {
-    let mut early_error_handler = EarlyErrorHandler::new(ErrorOutputType::default());
+/// Environment variable which overrides the sysroot used for analysis, `--sysroot` passed to rustc takes precedence
+const SYSROOT_VAR: &str = "LOCKCHECK_SYSROOT";
+
+/// Name of the file the lock filler is loaded from, this file doesn't exist on disk
+const FILLER_FILE_NAME: &str = "__lock_check_resolve.rs";
+
+/// Loads files from disk, except for the lock filler which is kept in memory
+///
+/// The filler is included into the crate root from its own file so none of its spans point at lines in the user's source
+struct FillerFileLoader {
+    filler_path: PathBuf,
+    filler: String,
+}
+
+impl FileLoader for FillerFileLoader {
+    fn file_exists(&self, path: &Path) -> bool {
+        path == self.filler_path || RealFileLoader.file_exists(path)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        if path == self.filler_path {
+            Ok(self.filler.clone())
+        } else {
+            RealFileLoader.read_file(path)
+        }
+    }
+
+    fn read_binary_file(&self, path: &Path) -> io::Result<Lrc<[u8]>> {
+        if path == self.filler_path {
+            Ok(self.filler.as_bytes().into())
+        } else {
+            RealFileLoader.read_binary_file(path)
+        }
+    }
+}
 
-    let full_args = std::env::args().collect::<Vec<_>>();
-    // rustc argument functions require first argument is stripped off
-    let args = full_args.get(1..).unwrap_or_default();
+/// Gets the sysroot from `LOCKCHECK_SYSROOT`, or from the rustc given with `--rustc` or in `RUSTC`
+///
+/// Without any of these rustc finds the sysroot of the toolchain lockcheck was built with
+fn sysroot_override(lockcheck_args: &Args) -> Result<Option<PathBuf>> {
+    if let Some(sysroot) = std::env::var_os(SYSROOT_VAR) {
+        return Ok(Some(sysroot.into()));
+    }
 
-    let args = arg_expand_all(&early_error_handler, args);
+    let Some(rustc) = lockcheck_args.rustc.clone().map(Into::into).or_else(|| std::env::var_os("RUSTC")) else {
+        return Ok(None);
+    };
+
+    let output = Command::new(&rustc)
+        .arg("--print=sysroot")
+        .output()
+        .with_context(|| format!("failed to run `{}`", rustc.to_string_lossy()))?;
+    if !output.status.success() {
+        bail!("`{} --print=sysroot` failed", rustc.to_string_lossy());
+    }
+
+    let sysroot = String::from_utf8(output.stdout)?;
+    Ok(Some(sysroot.trim().into()))
+}
+
+pub fn get_rustc_config(lock_check_config: &LockCheckConfig, lockcheck_args: &Args) -> Result<Config> {
+    let mut early_error_handler = EarlyErrorHandler::new(ErrorOutputType::default());
+
+    let args = arg_expand_all(&early_error_handler, &lockcheck_args.rustc_args);
     let Some(matches) = handle_options(&early_error_handler, &args) else {
         bail!("failed to generate rustc config");
     };
 
-    let sopts = config::build_session_options(&mut early_error_handler, &matches);
+    let mut sopts = config::build_session_options(&mut early_error_handler, &matches);
+    if lockcheck_args.tests {
+        // this is the same as passing `--test` to rustc, which sets `cfg(test)`
+        sopts.test = true;
+    }
+    if let Some(mir_opt_level) = lock_check_config.mir_opt_level {
+        if mir_opt_level > 4 {
+            bail!("`mir_opt_level` must be from 0 to 4, got {}", mir_opt_level);
+        }
+        sopts.unstable_opts.mir_opt_level = Some(mir_opt_level);
+    }
+    if let Some(inline_mir) = lock_check_config.inline_mir {
+        sopts.unstable_opts.inline_mir = Some(inline_mir);
+    }
+    if lockcheck_args.no_mir_opt {
+        if lock_check_config.mir_opt_level.is_some_and(|mir_opt_level| mir_opt_level != 0) {
+            early_error_handler.early_warn("`--no-mir-opt` overrides `mir_opt_level` in the lockcheck config");
+        }
+        if lock_check_config.inline_mir == Some(true) {
+            early_error_handler.early_warn("`--no-mir-opt` overrides `inline_mir` in the lockcheck config");
+        }
+        // optimized mir is still analysed, drop elaboration and other passes needed for codegen still run
+        sopts.unstable_opts.mir_opt_level = Some(0);
+        sopts.unstable_opts.inline_mir = Some(false);
+    }
+    if sopts.maybe_sysroot.is_none() {
+        sopts.maybe_sysroot = sysroot_override(lockcheck_args)?;
+    }
+    // rustc defaults to the 2015 edition, cargo always passes the edition but running lockcheck directly may not
+    if !matches.opt_present("edition") {
+        if let Some(edition) = &lock_check_config.edition {
+            sopts.edition = edition.parse()
+                .map_err(|_| anyhow!("invalid edition `{}` in lockcheck config", edition))?;
+        }
+    }
     let cfg = interface::parse_cfgspecs(&early_error_handler, matches.opt_strs("cfg"));
     let check_cfg = interface::parse_check_cfg(&early_error_handler, matches.opt_strs("check-cfg"));
 
@@ -29,9 +126,16 @@ pub fn get_rustc_config(lock_check_config: &LockCheckConfig) -> Result<Config> {
         bail!("no input filename given");
     };
 
+    // the filler is included from a file next to the crate root, so it is in the root module
+    // but diagnostics in it never point past the end of the user's source
+    let filler_path = std::fs::canonicalize(input_file)?
+        .with_file_name(FILLER_FILE_NAME);
     let mut file_data = std::fs::read_to_string(input_file)?;
-    let lock_resolve_filler = generate_lock_filler(&lock_check_config)?;
-    file_data.push_str(&lock_resolve_filler);
+    file_data.push_str(&format!("\ninclude!({:?});\n", filler_path));
+    let file_loader = FillerFileLoader {
+        filler_path,
+        filler: generate_lock_filler(&lock_check_config)?,
+    };
 
     Ok(Config {
         opts: sopts,
@@ -44,7 +148,7 @@ pub fn get_rustc_config(lock_check_config: &LockCheckConfig) -> Result<Config> {
         output_file: None,
         output_dir: None,
         ice_file: None,
-        file_loader: None,
+        file_loader: Some(Box::new(file_loader)),
         locale_resources: rustc_driver::DEFAULT_LOCALE_RESOURCES,
         lint_caps: rustc_hash::FxHashMap::default(),
         parse_sess_created: None,
@@ -3,23 +3,52 @@ use std::panic::{catch_unwind, AssertUnwindSafe, take_hook, set_hook};
 use rustc_span::def_id::DefId;
 use rustc_middle::mir::Body;
 use rustc_middle::ty::TyCtxt;
+use rustc_hir::def::DefKind;
+
+/// Why the mir of a body could not be obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingMir {
+    /// The definition has no body, or its mir is not encoded in the crate metadata
+    NoMir,
+    /// Building the mir panicked
+    Panicked,
+}
+
+impl MissingMir {
+    pub fn description(self) -> &'static str {
+        match self {
+            MissingMir::NoMir => "no mir available",
+            MissingMir::Panicked => "getting mir panicked",
+        }
+    }
+}
 
 pub trait TyCtxtExt<'tcx> {
     fn try_optimized_mir(self, def_id: DefId) -> Option<&'tcx Body<'tcx>>;
+    fn optimized_mir_or_reason(self, def_id: DefId) -> Result<&'tcx Body<'tcx>, MissingMir>;
+    fn is_const_initializer(self, def_id: DefId) -> bool;
 }
 
 impl<'tcx> TyCtxtExt<'tcx> for TyCtxt<'tcx> {
     // FIXME: this is an ugly hack to get optimized_mir without panicing if it doesn't exist
     // as far as I can tell, tctxt does not give us a version of optimized_mir that returns option instead of panicing
     fn try_optimized_mir(self, def_id: DefId) -> Option<&'tcx Body<'tcx>> {
+        self.optimized_mir_or_reason(def_id).ok()
+    }
+
+    /// Like `try_optimized_mir`, but says why the mir could not be obtained
+    fn optimized_mir_or_reason(self, def_id: DefId) -> Result<&'tcx Body<'tcx>, MissingMir> {
         let hir = self.hir();
 
         if let Some(local_def_id) = def_id.as_local() {
             // this will cause internal compiler error to print if we try to get mir
             // of local definition wich has no body, so check here first
             if hir.maybe_body_owned_by(local_def_id) == None {
-                return None;
+                return Err(MissingMir::NoMir);
             }
+        } else if !self.is_const_initializer(def_id) && !self.is_mir_available(def_id) {
+            // functions from other crates only have mir if they are generic or inline
+            return Err(MissingMir::NoMir);
         }
 
         let tcx = AssertUnwindSafe(self);
@@ -27,10 +56,23 @@ impl<'tcx> TyCtxtExt<'tcx> for TyCtxt<'tcx> {
         let prev_hook = take_hook();
         set_hook(Box::new(|_| {}));
 
-        let body = catch_unwind(|| tcx.optimized_mir(def_id)).ok();
+        // constants and statics have no optimized mir, so use the mir used to evaluate them instead
+        let body = if self.is_const_initializer(def_id) {
+            catch_unwind(|| tcx.mir_for_ctfe(def_id))
+        } else {
+            catch_unwind(|| tcx.optimized_mir(def_id))
+        };
 
         set_hook(prev_hook);
 
-        body
+        body.map_err(|_| MissingMir::Panicked)
+    }
+
+    /// Returns true if `def_id` is the initializer of a constant or static
+    fn is_const_initializer(self, def_id: DefId) -> bool {
+        matches!(
+            self.def_kind(def_id),
+            DefKind::Const | DefKind::AssocConst | DefKind::AnonConst | DefKind::InlineConst | DefKind::Static(_)
+        )
     }
 }
\ No newline at end of file
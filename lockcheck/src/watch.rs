@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Result, anyhow};
+
+use crate::analysis;
+use crate::args::Args;
+use crate::config::Config;
+
+/// How often source files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long files must stay unchanged before analysis is rerun
+///
+/// This stops multiple reruns when several files are saved in quick succession
+const DEBOUNCE_TIME: Duration = Duration::from_millis(300);
+
+/// Modification times of every rust source file in a directory tree
+type Snapshot = HashMap<PathBuf, SystemTime>;
+
+fn collect_snapshot(dir: &Path, snapshot: &mut Snapshot) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_snapshot(&path, snapshot)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            snapshot.insert(path.clone(), path.metadata()?.modified()?);
+        }
+    }
+
+    Ok(())
+}
+
+fn take_snapshot(dir: &Path) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::new();
+    collect_snapshot(dir, &mut snapshot)?;
+    Ok(snapshot)
+}
+
+/// Waits until a source file changes and then stays unchanged for the debounce time
+fn wait_for_change(dir: &Path, snapshot: Snapshot) -> Result<Snapshot> {
+    let mut snapshot = snapshot;
+    let mut changed = false;
+
+    loop {
+        thread::sleep(if changed { DEBOUNCE_TIME } else { POLL_INTERVAL });
+
+        let new_snapshot = take_snapshot(dir)?;
+        if new_snapshot != snapshot {
+            changed = true;
+            snapshot = new_snapshot;
+        } else if changed {
+            return Ok(snapshot);
+        }
+    }
+}
+
+/// Runs analysis, and then reruns it every time a source file in the crate changes
+pub fn watch(config: &Config, args: &Args) -> Result<()> {
+    let input_file = args.input_file()
+        .ok_or_else(|| anyhow!("no input filename given"))?;
+    let watch_dir = Path::new(input_file).parent()
+        .ok_or_else(|| anyhow!("could not determine directory of `{}`", input_file))?
+        .to_owned();
+
+    let mut snapshot = take_snapshot(&watch_dir)?;
+
+    loop {
+        // clear the terminal so only the latest report is shown
+        print!("\x1B[2J\x1B[1;1H");
+
+        // a compile error while editing should not stop watching
+        match rustc_driver::catch_fatal_errors(|| analysis::run(config, args)) {
+            Ok(Ok(status)) if status.error_emitted() => (),
+            Ok(Ok(_)) => println!("lockcheck: no deadlocks detected"),
+            Ok(Err(err)) => println!("{:?}", err),
+            Err(_) => (),
+        }
+
+        println!("lockcheck: watching `{}` for changes", watch_dir.display());
+
+        snapshot = wait_for_change(&watch_dir, snapshot)?;
+    }
+}
@@ -0,0 +1,94 @@
+#![no_std]
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::{Mutex, RwLock};
+
+pub struct SpinMutex<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    pub const fn new(data: T) -> Self {
+        SpinMutex {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SpinMutexGuard<'_, T> {
+        while self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+
+        SpinMutexGuard(self)
+    }
+}
+
+pub struct SpinMutexGuard<'a, T>(&'a SpinMutex<T>);
+
+impl<T> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.data.get() }
+    }
+}
+
+impl<T> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+struct Deadlock1;
+pub fn deadlock1() {
+    let mutex = SpinMutex::new(Deadlock1);
+    let _guard1 = mutex.lock();
+    let _guard2 = mutex.lock();
+}
+
+struct Okay1;
+pub fn okay1() {
+    let mutex = SpinMutex::new(Okay1);
+    let guard1 = mutex.lock();
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct DeadlockSpin;
+pub fn deadlock_spin(mutex: &Mutex<DeadlockSpin>) {
+    let _guard1 = mutex.lock();
+    let _guard2 = mutex.lock();
+}
+
+struct OkaySpin;
+pub fn okay_spin(mutex: &Mutex<OkaySpin>) {
+    let guard1 = mutex.lock();
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct DeadlockSpinRwLock;
+pub fn deadlock_spin_rwlock(lock: &RwLock<DeadlockSpinRwLock>) {
+    let _write_guard = lock.write();
+    let _read_guard = lock.read();
+}
+
+// read locks don't block each other
+struct OkaySpinRwLock;
+pub fn okay_spin_rwlock(lock: &RwLock<OkaySpinRwLock>) {
+    let _read_guard1 = lock.read();
+    let _read_guard2 = lock.read();
+}
\ No newline at end of file
@@ -10,3 +10,7 @@ cd test_crate
 
 cargo lockcheck
 # LD_LIBRARY_PATH="$(echo ~/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/lib)" cargo lockcheck
+
+cd ../no_std_crate
+
+cargo lockcheck
@@ -0,0 +1,7 @@
+use std::sync::Mutex;
+
+// locks `Mutex<u32>` then `Mutex<u64>`, which deadlocks with `second.rs`
+pub fn deadlock_crate_roots(a: &Mutex<u32>, b: &Mutex<u64>) {
+    let _a = a.lock();
+    let _b = b.lock();
+}
\ No newline at end of file
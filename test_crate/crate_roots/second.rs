@@ -0,0 +1,7 @@
+use std::sync::Mutex;
+
+// locks `Mutex<u64>` then `Mutex<u32>`, which deadlocks with `first.rs`
+pub fn deadlock_crate_roots(a: &Mutex<u32>, b: &Mutex<u64>) {
+    let _b = b.lock();
+    let _a = a.lock();
+}
\ No newline at end of file
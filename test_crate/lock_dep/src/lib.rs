@@ -0,0 +1,11 @@
+//! A dependency of `test_crate` which locks a mutex, used to test `analyze_deps`
+
+use std::sync::Mutex;
+
+pub struct DepCounter(pub u32);
+
+// `#[inline]` functions have their mir included in the crate metadata, so lockcheck can analyse them
+#[inline]
+pub fn increment(counter: &Mutex<DepCounter>) {
+    counter.lock().unwrap().0 += 1;
+}
\ No newline at end of file
@@ -0,0 +1,64 @@
+use tokio::sync::{Mutex, RwLock};
+
+struct DeadlockTokio;
+async fn deadlock_tokio(mutex: &Mutex<DeadlockTokio>) {
+    let _guard1 = mutex.lock().await;
+    let _guard2 = mutex.lock().await;
+}
+
+struct DeadlockTokioA;
+struct DeadlockTokioB;
+async fn deadlock_tokio_order1(mutex_a: &Mutex<DeadlockTokioA>, mutex_b: &Mutex<DeadlockTokioB>) {
+    let _guard1 = mutex_a.lock().await;
+    let _guard2 = mutex_b.lock().await;
+}
+
+async fn deadlock_tokio_order2(mutex_a: &Mutex<DeadlockTokioA>, mutex_b: &Mutex<DeadlockTokioB>) {
+    let _guard1 = mutex_b.lock().await;
+    let _guard2 = mutex_a.lock().await;
+}
+
+struct OkayTokio;
+async fn okay_tokio(mutex: &Mutex<OkayTokio>) {
+    let guard1 = mutex.lock().await;
+    drop(guard1);
+    let _guard2 = mutex.lock().await;
+}
+
+// dropping a different guard of the same guard type doesn't release the first lock
+struct DeadlockTokioOtherGuard;
+async fn deadlock_tokio_other_guard(mutex: &Mutex<DeadlockTokioOtherGuard>, other: &Mutex<u32>) {
+    let _guard1 = mutex.lock().await;
+    let other_guard = other.lock().await;
+    drop(other_guard);
+    let _guard2 = mutex.lock().await;
+}
+
+struct DeadlockDowngrade;
+async fn deadlock_downgrade(lock: &RwLock<DeadlockDowngrade>) {
+    let write_guard = lock.write().await;
+    // the lock is still held by the read guard
+    let _read_guard = write_guard.downgrade();
+    let _write_guard2 = lock.write().await;
+}
+
+struct OkayDowngrade;
+async fn okay_downgrade(lock: &RwLock<OkayDowngrade>) {
+    let write_guard = lock.write().await;
+    let read_guard = write_guard.downgrade();
+    drop(read_guard);
+    let _write_guard2 = lock.write().await;
+}
+
+struct WarnStdAcrossAwait;
+async fn warn_std_across_await(mutex: &std::sync::Mutex<WarnStdAcrossAwait>) {
+    let _guard = mutex.lock();
+    std::future::ready(()).await;
+}
+
+struct OkayStdAcrossAwait;
+async fn okay_std_across_await(mutex: &std::sync::Mutex<OkayStdAcrossAwait>) {
+    let guard = mutex.lock();
+    drop(guard);
+    std::future::ready(()).await;
+}
\ No newline at end of file
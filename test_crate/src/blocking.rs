@@ -0,0 +1,53 @@
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::process::Child;
+use std::time::Duration;
+
+struct WarnBlockingRecv;
+fn warn_blocking_recv(mutex: &Mutex<WarnBlockingRecv>, rx: &Receiver<u32>) {
+    let _guard = mutex.lock();
+    rx.recv().unwrap();
+}
+
+fn recv_value(rx: &Receiver<u32>) -> u32 {
+    rx.recv().unwrap()
+}
+
+struct WarnBlockingRecvHelper;
+fn warn_blocking_recv_helper(mutex: &Mutex<WarnBlockingRecvHelper>, rx: &Receiver<u32>) {
+    let _guard = mutex.lock();
+    recv_value(rx);
+}
+
+// `recv` is called inside the receiver's iterator in `std`, so the warning points at the loop
+struct WarnBlockingRecvIter;
+fn warn_blocking_recv_iter(mutex: &Mutex<WarnBlockingRecvIter>, rx: &Receiver<u32>) {
+    let _guard = mutex.lock();
+    for _value in rx.iter() {}
+}
+
+struct OkayBlockingRecv;
+fn okay_blocking_recv(mutex: &Mutex<OkayBlockingRecv>, rx: &Receiver<u32>) {
+    let guard = mutex.lock();
+    drop(guard);
+    rx.recv().unwrap();
+}
+
+struct WarnBlockingSleep;
+fn warn_blocking_sleep(mutex: &Mutex<WarnBlockingSleep>) {
+    let _guard = mutex.lock();
+    std::thread::sleep(Duration::from_millis(10));
+}
+
+struct WarnBlockingChildWait;
+fn warn_blocking_child_wait(mutex: &Mutex<WarnBlockingChildWait>, child: &mut Child) {
+    let _guard = mutex.lock();
+    let _ = child.wait();
+}
+
+struct OkayBlockingSleep;
+fn okay_blocking_sleep(mutex: &Mutex<OkayBlockingSleep>) {
+    let guard = mutex.lock();
+    drop(guard);
+    std::thread::sleep(Duration::from_millis(10));
+}
\ No newline at end of file
@@ -0,0 +1,11 @@
+use std::sync::Mutex;
+
+mod reverse;
+
+// the two orders of this cycle are in different files, so it is still reported with `--changed-files=src/changed_files/reverse.rs`
+pub struct DeadlockChangedFileA;
+pub struct DeadlockChangedFileB;
+fn deadlock_changed_file(a: &Mutex<DeadlockChangedFileA>, b: &Mutex<DeadlockChangedFileB>) {
+    let _guard1 = a.lock();
+    let _guard2 = b.lock();
+}
\ No newline at end of file
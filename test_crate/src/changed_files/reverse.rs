@@ -0,0 +1,8 @@
+use std::sync::Mutex;
+
+use super::{DeadlockChangedFileA, DeadlockChangedFileB};
+
+fn deadlock_changed_file_reverse(a: &Mutex<DeadlockChangedFileA>, b: &Mutex<DeadlockChangedFileB>) {
+    let _guard2 = b.lock();
+    let _guard1 = a.lock();
+}
\ No newline at end of file
@@ -0,0 +1,307 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A lock which is acquired by consuming a handle to the lock
+pub struct ValueLock<T>(Arc<(AtomicBool, T)>);
+
+impl<T> ValueLock<T> {
+    pub fn new(data: T) -> Self {
+        ValueLock(Arc::new((AtomicBool::new(false), data)))
+    }
+
+    pub fn handle(&self) -> Self {
+        ValueLock(self.0.clone())
+    }
+
+    pub fn lock(self) -> Result<ValueLockGuard<T>, ()> {
+        while self.0.0.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {}
+
+        Ok(ValueLockGuard(self))
+    }
+}
+
+pub struct ValueLockGuard<T>(ValueLock<T>);
+
+impl<T> Drop for ValueLockGuard<T> {
+    fn drop(&mut self) {
+        self.0.0.0.store(false, Ordering::Release);
+    }
+}
+
+struct DeadlockValueLock;
+fn deadlock_value_lock() {
+    let lock = ValueLock::new(DeadlockValueLock);
+    let _guard1 = lock.handle().lock();
+    let _guard2 = lock.handle().lock();
+}
+
+struct OkayValueLock;
+fn okay_value_lock() {
+    let lock = ValueLock::new(OkayValueLock);
+    let guard1 = lock.handle().lock();
+    drop(guard1);
+    let _guard2 = lock.handle().lock();
+}
+
+
+/// A lock whose lock method returns the guard directly instead of in a `Result`
+pub struct DirectLock<T> {
+    locked: AtomicBool,
+    data: T,
+}
+
+impl<T> DirectLock<T> {
+    pub fn new(data: T) -> Self {
+        DirectLock {
+            locked: AtomicBool::new(false),
+            data,
+        }
+    }
+
+    pub fn lock(&self) -> DirectLockGuard<'_, T> {
+        while self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {}
+
+        DirectLockGuard(self)
+    }
+
+    pub fn try_lock(&self) -> Result<DirectLockGuard<'_, T>, WouldBlock> {
+        match self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(DirectLockGuard(self)),
+            Err(_) => Err(WouldBlock),
+        }
+    }
+}
+
+/// Error returned when a `DirectLock` is already locked, this deliberately doesn't implement `Debug`
+pub struct WouldBlock;
+
+pub struct DirectLockGuard<'a, T>(&'a DirectLock<T>);
+
+impl<T> Drop for DirectLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+// reported as a warning, since `DirectLock` is configured with `severity = "warning"`
+struct DeadlockDirectLock;
+fn deadlock_direct_lock() {
+    let lock = DirectLock::new(DeadlockDirectLock);
+    let _guard1 = lock.lock();
+    let _guard2 = lock.lock();
+}
+
+struct OkayDirectLock;
+fn okay_direct_lock() {
+    let lock = DirectLock::new(OkayDirectLock);
+    let guard1 = lock.lock();
+    drop(guard1);
+    let _guard2 = lock.lock();
+}
+
+/// A lock which is locked with a free function instead of a method
+pub struct FreeFnLock<T> {
+    locked: AtomicBool,
+    data: T,
+}
+
+impl<T> FreeFnLock<T> {
+    pub fn new(data: T) -> Self {
+        FreeFnLock {
+            locked: AtomicBool::new(false),
+            data,
+        }
+    }
+}
+
+pub fn acquire<T>(lock: &FreeFnLock<T>) -> FreeFnLockGuard<'_, T> {
+    while lock.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {}
+
+    FreeFnLockGuard(lock)
+}
+
+pub struct FreeFnLockGuard<'a, T>(&'a FreeFnLock<T>);
+
+impl<T> Drop for FreeFnLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+struct DeadlockFreeFnLock;
+fn deadlock_free_fn_lock() {
+    let lock = FreeFnLock::new(DeadlockFreeFnLock);
+    let _guard1 = acquire(&lock);
+    let _guard2 = acquire(&lock);
+}
+
+struct OkayFreeFnLock;
+fn okay_free_fn_lock() {
+    let lock = FreeFnLock::new(OkayFreeFnLock);
+    let guard1 = acquire(&lock);
+    drop(guard1);
+    let _guard2 = acquire(&lock);
+}
+
+// reported as an error, since `FreeFnLock` is configured with `severity = "error"`, which is more severe than `DirectLock`'s
+struct DeadlockSeverityError;
+fn deadlock_severity_error1(direct_lock: &DirectLock<DeadlockSeverityError>, free_fn_lock: &FreeFnLock<DeadlockSeverityError>) {
+    let _guard1 = direct_lock.lock();
+    let _guard2 = acquire(free_fn_lock);
+}
+
+fn deadlock_severity_error2(direct_lock: &DirectLock<DeadlockSeverityError>, free_fn_lock: &FreeFnLock<DeadlockSeverityError>) {
+    let _guard1 = acquire(free_fn_lock);
+    let _guard2 = direct_lock.lock();
+}
+
+/// A lock which can be used in constant and static initializers
+pub struct ConstLock<T> {
+    data: T,
+}
+
+impl<T> ConstLock<T> {
+    pub const fn new(data: T) -> Self {
+        ConstLock {
+            data,
+        }
+    }
+
+    pub const fn lock(&self) -> ConstLockGuard<'_, T> {
+        ConstLockGuard(&self.data)
+    }
+}
+
+pub struct ConstLockGuard<'a, T>(&'a T);
+
+struct DeadlockStatic;
+static DEADLOCK_STATIC_LOCK: ConstLock<DeadlockStatic> = ConstLock::new(DeadlockStatic);
+static DEADLOCK_STATIC: () = {
+    let _guard1 = DEADLOCK_STATIC_LOCK.lock();
+    let _guard2 = DEADLOCK_STATIC_LOCK.lock();
+};
+
+struct OkayStatic;
+static OKAY_STATIC_LOCK: ConstLock<OkayStatic> = ConstLock::new(OkayStatic);
+static OKAY_STATIC: () = {
+    {
+        let _guard1 = OKAY_STATIC_LOCK.lock();
+    }
+    let _guard2 = OKAY_STATIC_LOCK.lock();
+};
+
+/// Raw lock used by `RawFirstLock`
+pub struct SpinRaw(AtomicBool);
+
+/// A lock whose first type parameter is the raw lock, and the guarded value is the second type parameter
+pub struct RawFirstLock<R, T> {
+    raw: R,
+    data: T,
+}
+
+impl<T> RawFirstLock<SpinRaw, T> {
+    pub fn new(data: T) -> Self {
+        RawFirstLock {
+            raw: SpinRaw(AtomicBool::new(false)),
+            data,
+        }
+    }
+}
+
+impl<R, T> RawFirstLock<R, T> {
+    pub fn lock(&self) -> RawFirstLockGuard<'_, R, T> {
+        RawFirstLockGuard(self)
+    }
+}
+
+pub struct RawFirstLockGuard<'a, R, T>(&'a RawFirstLock<R, T>);
+
+impl<R, T> Drop for RawFirstLockGuard<'_, R, T> {
+    fn drop(&mut self) {}
+}
+
+struct DeadlockRawFirst;
+fn deadlock_raw_first(lock: &RawFirstLock<SpinRaw, DeadlockRawFirst>) {
+    let _guard1 = lock.lock();
+    let _guard2 = lock.lock();
+}
+
+struct OkayRawFirstA;
+struct OkayRawFirstB;
+fn okay_raw_first(lock_a: &RawFirstLock<SpinRaw, OkayRawFirstA>, lock_b: &RawFirstLock<SpinRaw, OkayRawFirstB>) {
+    let _guard1 = lock_a.lock();
+    let _guard2 = lock_b.lock();
+}
+
+/// A lock whose constructor takes no arguments, and starts with the default value
+pub struct DefaultLock<T> {
+    locked: AtomicBool,
+    data: T,
+}
+
+impl<T: Default> DefaultLock<T> {
+    pub fn new() -> Self {
+        DefaultLock {
+            locked: AtomicBool::new(false),
+            data: T::default(),
+        }
+    }
+}
+
+impl<T> DefaultLock<T> {
+    pub fn lock(&self) -> DefaultLockGuard<'_, T> {
+        while self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {}
+
+        DefaultLockGuard(self)
+    }
+}
+
+pub struct DefaultLockGuard<'a, T>(&'a DefaultLock<T>);
+
+impl<T> Drop for DefaultLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+#[derive(Default)]
+struct DeadlockDefaultLock;
+fn deadlock_default_lock() {
+    let lock = DefaultLock::<DeadlockDefaultLock>::new();
+    let _guard1 = lock.lock();
+    let _guard2 = lock.lock();
+}
+
+// reported even though the locks are never locked in the other order,
+// since `DefaultLock` is configured with `rank = 1` and is locked while a `FreeFnLock` with `rank = 2` is held
+#[derive(Default)]
+struct DeadlockRank;
+fn deadlock_lock_rank(free_fn_lock: &FreeFnLock<DeadlockRank>, default_lock: &DefaultLock<DeadlockRank>) {
+    let _guard1 = acquire(free_fn_lock);
+    let _guard2 = default_lock.lock();
+}
+
+#[derive(Default)]
+struct OkayRank;
+fn okay_lock_rank(free_fn_lock: &FreeFnLock<OkayRank>, default_lock: &DefaultLock<OkayRank>) {
+    let _guard1 = default_lock.lock();
+    let _guard2 = acquire(free_fn_lock);
+}
+
+// the `try_lock` target resolves even though `WouldBlock` doesn't implement `Debug`
+struct OkayTryLock;
+fn okay_try_lock() {
+    let lock = DirectLock::new(OkayTryLock);
+    if let Ok(guard) = lock.try_lock() {
+        drop(guard);
+    }
+    let _guard = lock.try_lock();
+}
+
+// `try_lock` returns an error instead of waiting while the lock is held, so it never deadlocks
+struct OkayTryLockWhileHeld;
+fn okay_try_lock_while_held() {
+    let lock = DirectLock::new(OkayTryLockWhileHeld);
+    let _guard1 = lock.lock();
+    let _guard2 = lock.try_lock();
+}
\ No newline at end of file
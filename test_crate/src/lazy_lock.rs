@@ -0,0 +1,43 @@
+use std::sync::{LazyLock, Mutex};
+
+struct DeadlockLazyLockA;
+struct DeadlockLazyLockB;
+static DEADLOCK_LAZY_LOCK_MUTEX: Mutex<DeadlockLazyLockA> = Mutex::new(DeadlockLazyLockA);
+static DEADLOCK_LAZY_LOCK: LazyLock<DeadlockLazyLockB> = LazyLock::new(|| {
+    let _guard = DEADLOCK_LAZY_LOCK_MUTEX.lock();
+    DeadlockLazyLockB
+});
+
+// the init closure was cast to a function pointer, so it is found from the static's initializer
+fn deadlock_lazy_lock() {
+    let _guard = DEADLOCK_LAZY_LOCK_MUTEX.lock();
+    LazyLock::force(&DEADLOCK_LAZY_LOCK);
+}
+
+struct DeadlockLazyLockDerefA;
+struct DeadlockLazyLockDerefB;
+fn deadlock_lazy_lock_deref(mutex: &Mutex<DeadlockLazyLockDerefA>) {
+    let lazy = LazyLock::new(|| {
+        let _guard = mutex.lock();
+        DeadlockLazyLockDerefB
+    });
+
+    let _guard = mutex.lock();
+    let _value: &DeadlockLazyLockDerefB = &lazy;
+}
+
+struct DeadlockLazyLockReentrant(u32);
+static DEADLOCK_LAZY_LOCK_REENTRANT: LazyLock<DeadlockLazyLockReentrant> = LazyLock::new(|| {
+    DeadlockLazyLockReentrant(DEADLOCK_LAZY_LOCK_REENTRANT.0 + 1)
+});
+
+fn deadlock_lazy_lock_reentrant() -> u32 {
+    DEADLOCK_LAZY_LOCK_REENTRANT.0
+}
+
+struct OkayLazyLock;
+static OKAY_LAZY_LOCK: LazyLock<OkayLazyLock> = LazyLock::new(|| OkayLazyLock);
+fn okay_lazy_lock() {
+    LazyLock::force(&OKAY_LAZY_LOCK);
+    let _value: &OkayLazyLock = &OKAY_LAZY_LOCK;
+}
\ No newline at end of file
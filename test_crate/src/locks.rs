@@ -12,6 +12,13 @@ fn deadlock2<T>(a: T) {
     let _guard = lock.lock();
 }
 
+fn use_deadlock2() {
+    // this deadlock should still only be reported once
+    deadlock2(0u8);
+    deadlock2("hello");
+    deadlock2(vec![1, 2, 3]);
+}
+
 fn deadlock3a<U, V>(a: U, b: V) {
     let lock1 = Mutex::new(a);
     let lock2 = Mutex::new(b);
@@ -1,7 +1,19 @@
+#![feature(lazy_cell)]
+
+mod async_locks;
+mod blocking;
+mod changed_files;
+mod custom_locks;
+mod lazy_lock;
 mod locks;
+mod once_lock;
+mod ref_cell;
+mod rw_lock;
+mod scope_guard;
 mod tester;
+mod trait_locks;
 
-use std::sync::{Mutex, Arc, MutexGuard, LockResult};
+use std::sync::{Mutex, Arc, MutexGuard, LockResult, PoisonError};
 use parking_lot::Mutex as OtherMutex;
 
 struct Okay1;
@@ -58,6 +70,48 @@ fn deadlock4(n: i32) {
     }
 }
 
+struct Deadlock4b;
+fn deadlock4b(n: i32) {
+    let mutex = Mutex::new(Deadlock4b);
+    let guard1 = mutex.lock();
+
+    if n == 2 {
+        drop(guard1);
+    }
+
+    let _guard2 = mutex.lock();
+}
+
+// the guard is held on both branches, so the second lock doesn't depend on the condition once they merge
+struct Deadlock4c;
+fn deadlock4c(n: i32) {
+    let mutex = Mutex::new(Deadlock4c);
+    let _guard1 = mutex.lock();
+
+    if n == 2 {
+        println!("happy birthday");
+    } else {
+        println!("sad day");
+    }
+
+    let _guard2 = mutex.lock();
+}
+
+struct Okay4b;
+fn okay4b(n: i32) {
+    let mutex = Mutex::new(Okay4b);
+    let guard1 = mutex.lock();
+
+    if n == 2 {
+        drop(guard1);
+    } else {
+        println!("not 2");
+        drop(guard1);
+    }
+
+    let _guard2 = mutex.lock();
+}
+
 struct Okay5a;
 struct Okay5b;
 fn okay5(n: i32) {
@@ -88,6 +142,21 @@ fn deadlock5(n: i32) {
     }
 }
 
+struct Deadlock5c;
+struct Deadlock5d;
+fn deadlock5b() {
+    let mutex1 = Mutex::new(Deadlock5c);
+    let mutex2 = Mutex::new(Deadlock5d);
+
+    {
+        let _guard1 = mutex1.lock();
+        let _guard2 = mutex2.lock();
+    }
+
+    let _guard2 = mutex2.lock();
+    let _guard1 = mutex1.lock();
+}
+
 struct Deadlock6;
 fn deadlock6() {
     let mutex = Mutex::new(Deadlock6);
@@ -112,6 +181,53 @@ fn okay6() {
     let _guard2 = mutex.lock();
 }
 
+// the guard escapes two nested blocks
+struct Deadlock6b;
+fn deadlock6b() {
+    let mutex = Mutex::new(Deadlock6b);
+
+    let _guard = {
+        let outer = {
+            let inner = mutex.lock();
+            inner
+        };
+        outer
+    };
+
+    let _guard2 = mutex.lock();
+}
+
+struct Okay6b;
+fn okay6b() {
+    let mutex = Mutex::new(Okay6b);
+
+    {
+        {
+            let _guard1 = mutex.lock();
+        }
+        let _guard2 = mutex.lock();
+    }
+
+    let _guard3 = mutex.lock();
+}
+
+// the guard is moved out of the innermost block, but dropped at the end of the block around it
+struct Okay6c;
+fn okay6c() {
+    let mutex = Mutex::new(Okay6c);
+
+    {
+        let _outer = {
+            {
+                let inner = mutex.lock();
+                inner
+            }
+        };
+    }
+
+    let _guard = mutex.lock();
+}
+
 struct Okay7;
 fn okay7() {
     let mutex = Mutex::new(Okay7);
@@ -166,6 +282,69 @@ fn okay9() {
     let _guard2 = mutex.lock();
 }
 
+struct Outer<'a, T> {
+    _inner: Inner<'a, T>,
+}
+
+struct Inner<'a, T> {
+    _guard: LockResult<MutexGuard<'a, T>>,
+}
+
+// the lock result is in a field of a field
+struct Deadlock9b;
+fn deadlock9b() {
+    let mutex = Mutex::new(Deadlock9b);
+
+    let _outer = Outer {
+        _inner: Inner {
+            _guard: mutex.lock(),
+        },
+    };
+
+    let _guard2 = mutex.lock();
+}
+
+struct Okay9b;
+fn okay9b() {
+    let mutex = Mutex::new(Okay9b);
+
+    let outer = Outer {
+        _inner: Inner {
+            _guard: mutex.lock(),
+        },
+    };
+
+    drop(outer);
+
+    let _guard2 = mutex.lock();
+}
+
+struct LaterGuard<'a, T> {
+    guard: Option<LockResult<MutexGuard<'a, T>>>,
+}
+
+// the lock result is assigned to the field after the struct is created
+struct Deadlock9c;
+fn deadlock9c() {
+    let mutex = Mutex::new(Deadlock9c);
+
+    let mut holder = LaterGuard { guard: None };
+    holder.guard = Some(mutex.lock());
+
+    let _guard2 = mutex.lock();
+}
+
+struct Okay9c;
+fn okay9c() {
+    let mutex = Mutex::new(Okay9c);
+
+    let mut holder = LaterGuard { guard: None };
+    holder.guard = Some(mutex.lock());
+    holder.guard = None;
+
+    let _guard2 = mutex.lock();
+}
+
 struct Deadlock10;
 fn deadlock10() {
     let mutex = Mutex::new(Deadlock10);
@@ -174,6 +353,29 @@ fn deadlock10() {
     let _guard2 = mutex.lock().unwrap();
 }
 
+// the guard is borrowed in a temporary before being moved into the binding,
+// which optimized mir can turn into a copy of the temporary
+struct Deadlock10b;
+fn deadlock10b(mutex: &Mutex<Deadlock10b>) {
+    let _guard1 = {
+        let temp = mutex.lock().unwrap();
+        let _size = std::mem::size_of_val(&temp);
+        temp
+    };
+    let _guard2 = mutex.lock();
+}
+
+struct Okay10b;
+fn okay10b(mutex: &Mutex<Okay10b>) {
+    let guard1 = {
+        let temp = mutex.lock().unwrap();
+        let _size = std::mem::size_of_val(&temp);
+        temp
+    };
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
 struct Deadlock11;
 fn deadlock11() {
     fn inner() {
@@ -184,7 +386,6 @@ fn deadlock11() {
     }
 }
 
-// FIXME: this is not detected
 struct Deadlock12;
 fn deadlock12() {
     let a = || {
@@ -247,6 +448,77 @@ fn deadlock14() {
     let _guard2 = inner(&mutex);
 }
 
+// two different helpers return guards of the same lock class
+struct Deadlock14b;
+fn deadlock14b() {
+    fn lock_first(mutex: &Mutex<Deadlock14b>) -> MutexGuard<Deadlock14b> {
+        mutex.lock().unwrap()
+    }
+
+    fn lock_second(mutex: &Mutex<Deadlock14b>) -> CustomGuard<Deadlock14b> {
+        CustomGuard(mutex.lock().unwrap())
+    }
+
+    let mutex = Mutex::new(Deadlock14b);
+    let _guard1 = lock_first(&mutex);
+    let _guard2 = lock_second(&mutex);
+}
+
+struct Okay14b;
+fn okay14b() {
+    fn lock_first(mutex: &Mutex<Okay14b>) -> MutexGuard<Okay14b> {
+        mutex.lock().unwrap()
+    }
+
+    fn lock_second(mutex: &Mutex<Okay14b>) -> CustomGuard<Okay14b> {
+        CustomGuard(mutex.lock().unwrap())
+    }
+
+    let mutex = Mutex::new(Okay14b);
+    let guard1 = lock_first(&mutex);
+    drop(guard1);
+    let _guard2 = lock_second(&mutex);
+}
+
+// the wrapper's lock call is inlined into the caller when mir inlining is enabled
+struct Deadlock14c;
+fn deadlock14c() {
+    #[inline(always)]
+    fn lock(mutex: &Mutex<Deadlock14c>) -> MutexGuard<Deadlock14c> {
+        mutex.lock().unwrap()
+    }
+
+    let mutex = Mutex::new(Deadlock14c);
+    let _guard1 = lock(&mutex);
+    let _guard2 = lock(&mutex);
+}
+
+// the wrapper is never inlined, so the guard is followed through its return place
+struct Deadlock14d;
+fn deadlock14d() {
+    #[inline(never)]
+    fn lock(mutex: &Mutex<Deadlock14d>) -> MutexGuard<Deadlock14d> {
+        mutex.lock().unwrap()
+    }
+
+    let mutex = Mutex::new(Deadlock14d);
+    let _guard1 = lock(&mutex);
+    let _guard2 = lock(&mutex);
+}
+
+struct Okay14c;
+fn okay14c() {
+    #[inline(always)]
+    fn lock(mutex: &Mutex<Okay14c>) -> MutexGuard<Okay14c> {
+        mutex.lock().unwrap()
+    }
+
+    let mutex = Mutex::new(Okay14c);
+    let guard1 = lock(&mutex);
+    drop(guard1);
+    let _guard2 = lock(&mutex);
+}
+
 struct Okay15;
 fn okay15() {
     let mutex = Mutex::new(Okay15);
@@ -285,4 +557,669 @@ fn deadlock16c() {
     let guard2 = mutexa.lock();
 }
 
+trait Held {}
+
+struct GuardHolder<'a, T>(MutexGuard<'a, T>);
+impl<T> Held for GuardHolder<'_, T> {}
+
+struct Deadlock17;
+fn deadlock17() {
+    let mutex = Mutex::new(Deadlock17);
+    let _held: Box<dyn Held + '_> = Box::new(GuardHolder(mutex.lock().unwrap()));
+    let _guard2 = mutex.lock();
+}
+
+struct Okay17;
+fn okay17() {
+    let mutex = Mutex::new(Okay17);
+    let held: Box<dyn Held + '_> = Box::new(GuardHolder(mutex.lock().unwrap()));
+    drop(held);
+    let _guard2 = mutex.lock();
+}
+
+// let else requires the 2021 edition, which is taken from Cargo.toml when lockcheck is run directly
+struct Deadlock18;
+fn deadlock18(mutex: Option<&Mutex<Deadlock18>>) {
+    let Some(mutex) = mutex else {
+        return;
+    };
+
+    let _guard1 = mutex.lock();
+    let _guard2 = mutex.lock();
+}
+
+struct Okay18;
+fn okay18(mutex: Option<&Mutex<Okay18>>) {
+    let Some(mutex) = mutex else {
+        return;
+    };
+
+    let guard1 = mutex.lock();
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock19;
+fn deadlock19() {
+    fn inner(mutex: &Mutex<Deadlock19>) -> (MutexGuard<'_, Deadlock19>, u32) {
+        (mutex.lock().unwrap(), 19)
+    }
+
+    let mutex = Mutex::new(Deadlock19);
+    let (_guard1, _n) = inner(&mutex);
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock19b;
+fn deadlock19b() {
+    fn inner(mutex: &Mutex<Deadlock19b>) -> (MutexGuard<'_, Deadlock19b>, String) {
+        (mutex.lock().unwrap(), String::from("19b"))
+    }
+
+    let mutex = Mutex::new(Deadlock19b);
+    let pair = inner(&mutex);
+    // only the string is dropped here, the guard is still held
+    drop(pair.1);
+    let _guard2 = mutex.lock();
+}
+
+struct Okay19;
+fn okay19() {
+    fn inner(mutex: &Mutex<Okay19>) -> (MutexGuard<'_, Okay19>, u32) {
+        (mutex.lock().unwrap(), 19)
+    }
+
+    let mutex = Mutex::new(Okay19);
+    let (guard1, _n) = inner(&mutex);
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+// the guard could be released through a mutable reference, so both of these are reported with a lower confidence
+struct Deadlock20;
+fn deadlock20() {
+    fn peek(guard: &mut MutexGuard<'_, Deadlock20>) -> &mut Deadlock20 {
+        &mut *guard
+    }
+
+    let mutex = Mutex::new(Deadlock20);
+    let mut guard1 = mutex.lock().unwrap();
+    peek(&mut guard1);
+    let _guard2 = mutex.lock();
+}
+
+// `release` does release the guard, but calls taking a mutable reference to the guard aren't followed
+struct Deadlock20b;
+fn deadlock20b() {
+    fn release(guard: &mut Option<MutexGuard<'_, Deadlock20b>>) {
+        guard.take();
+    }
+
+    let mutex = Mutex::new(Deadlock20b);
+    let mut guard1 = Some(mutex.lock().unwrap());
+    release(&mut guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock21;
+fn deadlock21a(mutex: &Mutex<Deadlock21>, n: u32) {
+    let _guard = mutex.lock();
+    deadlock21b(mutex, n);
+}
+
+fn deadlock21b(mutex: &Mutex<Deadlock21>, n: u32) {
+    if n > 0 {
+        deadlock21a(mutex, n - 1);
+    }
+}
+
+struct Deadlock22;
+fn deadlock22(items: &[u32]) {
+    let mutex = Mutex::new(Deadlock22);
+    let _guard1 = mutex.lock();
+    items.iter().for_each(|_| {
+        let _guard2 = mutex.lock();
+    });
+}
+
+struct Okay22;
+fn okay22(items: &[u32]) {
+    let mutex = Mutex::new(Okay22);
+    let guard1 = mutex.lock();
+    drop(guard1);
+    items.iter().for_each(|_| {
+        let _guard2 = mutex.lock();
+    });
+}
+
+struct Deadlock23;
+impl Deadlock23 {
+    fn ready(&self) -> bool {
+        true
+    }
+}
+
+fn deadlock23() {
+    let mutex = Mutex::new(Deadlock23);
+    let guard1 = mutex.lock().unwrap();
+    match guard1 {
+        _ if guard1.ready() => {
+            let _guard2 = mutex.lock();
+        },
+        _ => (),
+    }
+}
+
+struct Okay23;
+impl Okay23 {
+    fn ready(&self) -> bool {
+        true
+    }
+}
+
+fn okay23() {
+    let mutex = Mutex::new(Okay23);
+    let guard1 = mutex.lock().unwrap();
+    match guard1 {
+        guard if guard.ready() => {
+            drop(guard);
+            let _guard2 = mutex.lock();
+        },
+        _ => (),
+    }
+}
+
+fn with_callback(callback: &dyn Fn()) {
+    callback();
+}
+
+struct Deadlock24;
+fn deadlock24() {
+    let mutex = Mutex::new(Deadlock24);
+    let _guard1 = mutex.lock();
+    with_callback(&|| {
+        let _guard2 = mutex.lock();
+    });
+}
+
+struct Deadlock24b;
+fn lock_deadlock24b(mutex: &Mutex<Deadlock24b>) {
+    let _guard = mutex.lock();
+}
+
+fn call_with_mutex(mutex: &Mutex<Deadlock24b>, callback: fn(&Mutex<Deadlock24b>)) {
+    callback(mutex);
+}
+
+fn deadlock24b() {
+    let mutex = Mutex::new(Deadlock24b);
+    let _guard1 = mutex.lock();
+    call_with_mutex(&mutex, lock_deadlock24b);
+}
+
+struct Okay24;
+fn okay24() {
+    let mutex = Mutex::new(Okay24);
+    let guard1 = mutex.lock();
+    drop(guard1);
+    with_callback(&|| {
+        let _guard2 = mutex.lock();
+    });
+}
+
+struct Deadlock25;
+fn deadlock25() {
+    let mutex = Mutex::new(Deadlock25);
+    let mut guard = mutex.lock();
+    // the new guard is locked before the old guard is dropped
+    guard = mutex.lock();
+    drop(guard);
+}
+
+struct Okay25;
+fn okay25() {
+    let mutex = Mutex::new(Okay25);
+    let mut guard = Some(mutex.lock());
+    guard = None;
+    let _guard2 = mutex.lock();
+    drop(guard);
+}
+
+struct Deadlock26;
+fn deadlock26() {
+    let mutex = Mutex::new(Deadlock26);
+    let _guard1 = Some(mutex.lock().unwrap()).map(|guard| guard);
+    let _guard2 = mutex.lock();
+}
+
+struct Okay26;
+fn okay26() {
+    let mutex = Mutex::new(Okay26);
+    let guard1 = Some(mutex.lock().unwrap()).map(|guard| guard);
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock27A;
+struct Deadlock27B;
+
+// locks another mutex when dropped, while the wrapped guard is still held
+struct RelockOnDrop<'a> {
+    _guard: MutexGuard<'a, Deadlock27A>,
+    other: &'a Mutex<Deadlock27B>,
+}
+
+impl Drop for RelockOnDrop<'_> {
+    fn drop(&mut self) {
+        let _guard = self.other.lock();
+    }
+}
+
+fn deadlock27(a: &Mutex<Deadlock27A>, b: &Mutex<Deadlock27B>) {
+    let _wrapper = RelockOnDrop {
+        _guard: a.lock().unwrap(),
+        other: b,
+    };
+}
+
+fn deadlock27_reverse(a: &Mutex<Deadlock27A>, b: &Mutex<Deadlock27B>) {
+    let _b = b.lock();
+    let _a = a.lock();
+}
+
+struct Okay27A;
+struct Okay27B;
+
+struct OkayRelockOnDrop<'a> {
+    _guard: MutexGuard<'a, Okay27A>,
+    other: &'a Mutex<Okay27B>,
+}
+
+impl Drop for OkayRelockOnDrop<'_> {
+    fn drop(&mut self) {
+        let _guard = self.other.lock();
+    }
+}
+
+fn okay27(a: &Mutex<Okay27A>, b: &Mutex<Okay27B>) {
+    let wrapper = OkayRelockOnDrop {
+        _guard: a.lock().unwrap(),
+        other: b,
+    };
+    drop(wrapper);
+    let _b = b.lock();
+}
+
+macro_rules! lock_pair {
+    ($first:expr, $second:expr) => {{
+        let _first = $first.lock();
+        let _second = $second.lock();
+    }};
+}
+
+struct Deadlock28A;
+struct Deadlock28B;
+fn deadlock28(a: &Mutex<Deadlock28A>, b: &Mutex<Deadlock28B>) {
+    lock_pair!(a, b);
+    lock_pair!(b, a);
+}
+
+macro_rules! lock_twice {
+    ($mutex:expr) => {{
+        let _first = $mutex.lock();
+        let _second = $mutex.lock();
+    }};
+}
+
+// both lock calls are in one expansion, so they are shown inside the macro
+struct Deadlock28b;
+fn deadlock28b(mutex: &Mutex<Deadlock28b>) {
+    lock_twice!(mutex);
+}
+
+// the lifetimes of the guarded references differ, but they are still the same lock class
+struct Deadlock29;
+fn deadlock29<'a, 'b>(first: &Mutex<&'a Deadlock29>, second: &Mutex<&'b Deadlock29>) {
+    let _guard1 = first.lock();
+    let _guard2 = second.lock();
+}
+
+// the guard is only borrowed by the helper, so it is still held while the helper locks
+struct Deadlock30A;
+struct Deadlock30B;
+fn deadlock30(a: &Mutex<Deadlock30A>, b: &Mutex<Deadlock30B>) {
+    fn uses_guard_ref(_guard: &MutexGuard<Deadlock30A>, b: &Mutex<Deadlock30B>) {
+        let _b = b.lock();
+    }
+
+    let guard = a.lock().unwrap();
+    uses_guard_ref(&guard, b);
+}
+
+fn deadlock30_reverse(a: &Mutex<Deadlock30A>, b: &Mutex<Deadlock30B>) {
+    let _b = b.lock();
+    let _a = a.lock();
+}
+
+struct Okay30A;
+struct Okay30B;
+fn okay30(a: &Mutex<Okay30A>, b: &Mutex<Okay30B>) {
+    fn uses_guard_ref(_guard: &MutexGuard<Okay30A>, b: &Mutex<Okay30B>) {
+        let _b = b.lock();
+    }
+
+    let guard = a.lock().unwrap();
+    uses_guard_ref(&guard, b);
+}
+
+// the guard is recovered from the poison error, so it is held in both arms
+struct Deadlock31;
+fn deadlock31(mutex: &Mutex<Deadlock31>) {
+    let _guard1 = match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock31b;
+fn deadlock31b(mutex: &Mutex<Deadlock31b>) {
+    let _guard1 = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    let _guard2 = mutex.lock();
+}
+
+struct Okay31;
+fn okay31(mutex: &Mutex<Okay31>) {
+    let guard1 = match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct Deadlock32;
+fn deadlock32(mutex: &Mutex<Deadlock32>) {
+    let _guards = [mutex.lock().unwrap()];
+    let _guard2 = mutex.lock();
+}
+
+struct Okay32;
+fn okay32(mutex: &Mutex<Okay32>) {
+    let guards = [mutex.lock().unwrap()];
+    drop(guards);
+    let _guard2 = mutex.lock();
+}
+
+// the scrutinee guard is a temporary which lives until the end of the match
+struct Deadlock33;
+fn deadlock33(mutex: &Mutex<Deadlock33>) {
+    match mutex.lock().unwrap() {
+        _ => {
+            let _guard2 = mutex.lock();
+        },
+    }
+}
+
+struct Okay33;
+fn okay33(mutex: &Mutex<Okay33>) {
+    match mutex.lock().unwrap() {
+        guard => drop(guard),
+    }
+    let _guard2 = mutex.lock();
+}
+
+// the guard is moved between many locals on different branches before the lock is taken again
+struct Deadlock34;
+fn deadlock34(mutex: &Mutex<Deadlock34>, flags: &[bool; 4]) {
+    let mut guard = mutex.lock().unwrap();
+    for flag in flags {
+        let moved = guard;
+        guard = if *flag {
+            let first = moved;
+            first
+        } else {
+            let second = moved;
+            let third = second;
+            third
+        };
+    }
+    let _guard2 = mutex.lock();
+    drop(guard);
+}
+
+// with `--entry-points` this notes the thread the deadlock is reachable from
+struct DeadlockThread;
+fn deadlock_thread(mutex: Arc<Mutex<DeadlockThread>>) {
+    std::thread::spawn(move || {
+        let _guard1 = mutex.lock();
+        let _guard2 = mutex.lock();
+    });
+}
+
+// the lock method is called with UFCS instead of method call syntax
+struct Deadlock35;
+fn deadlock35(mutex: &Mutex<Deadlock35>) {
+    let _guard1 = mutex.lock();
+    let _guard2 = Mutex::lock(mutex);
+}
+
+struct Okay35;
+fn okay35(mutex: &Mutex<Okay35>) {
+    let guard1 = Mutex::lock(mutex);
+    drop(guard1);
+    let _guard2 = mutex.lock();
+}
+
+// temporary locks are a new instance each time, so they can't deadlock with another lock of the same class
+struct Okay36;
+fn okay36(mutex: &Mutex<Okay36>) {
+    let _guard = mutex.lock();
+    let _poisoned = Mutex::new(Okay36).lock().is_err();
+    drop((Mutex::new(Okay36).lock(), Mutex::new(Okay36).lock()));
+}
+
+// the guard is held by the closure it was moved into until the closure is called or dropped
+struct Deadlock37;
+fn deadlock37(mutex: &Mutex<Deadlock37>) {
+    let guard = mutex.lock();
+    let _release = move || drop(guard);
+    let _guard2 = mutex.lock();
+}
+
+struct CallbackHolder<F: FnOnce()> {
+    callback: F,
+}
+
+struct Deadlock37b;
+fn deadlock37b(mutex: &Mutex<Deadlock37b>) {
+    let guard = mutex.lock();
+    let _holder = CallbackHolder {
+        callback: move || drop(guard),
+    };
+    let _guard2 = mutex.lock();
+}
+
+struct Okay37;
+fn okay37(mutex: &Mutex<Okay37>) {
+    let guard = mutex.lock();
+    let release = move || drop(guard);
+    release();
+    let _guard2 = mutex.lock();
+}
+
+// the helper locks and unlocks the mutex, then locks it again and returns the guard,
+// which is held when the caller locks again
+struct Deadlock38;
+fn relock38(mutex: &Mutex<Deadlock38>) -> MutexGuard<'_, Deadlock38> {
+    drop(mutex.lock());
+    mutex.lock().unwrap()
+}
+
+fn deadlock38(mutex: &Mutex<Deadlock38>) {
+    let _guard1 = relock38(mutex);
+    let _guard2 = mutex.lock();
+}
+
+// both locks in the second call happen while the guard returned by the first call is held
+struct Deadlock38b;
+fn relock38b(mutex: &Mutex<Deadlock38b>) -> MutexGuard<'_, Deadlock38b> {
+    drop(mutex.lock());
+    mutex.lock().unwrap()
+}
+
+fn deadlock38b(mutex: &Mutex<Deadlock38b>) {
+    let _guard1 = relock38b(mutex);
+    let _guard2 = relock38b(mutex);
+}
+
+struct Okay38;
+fn relock_okay38(mutex: &Mutex<Okay38>) -> MutexGuard<'_, Okay38> {
+    drop(mutex.lock());
+    mutex.lock().unwrap()
+}
+
+fn okay38(mutex: &Mutex<Okay38>) {
+    let guard1 = relock_okay38(mutex);
+    drop(guard1);
+    let _guard2 = relock_okay38(mutex);
+}
+
+// a child node is locked while its parent is held, both are the same lock class,
+// so this is reported with a note that the locks may be different nodes
+struct DeadlockTreeNode {
+    children: Vec<Arc<Mutex<DeadlockTreeNode>>>,
+}
+
+fn deadlock_tree_node(node: &Mutex<DeadlockTreeNode>) {
+    let parent = node.lock().unwrap();
+    for child in parent.children.iter() {
+        let _child = child.lock();
+    }
+}
+
+struct FieldLocks<T> {
+    a: Mutex<T>,
+    b: Mutex<T>,
+}
+
+// the same field is locked twice
+struct Deadlock39;
+fn deadlock39(locks: &FieldLocks<Deadlock39>) {
+    let _guard1 = locks.a.lock();
+    let _guard2 = locks.a.lock();
+}
+
+// different fields are different locks, even though they are the same lock class
+struct Okay39;
+fn okay39(locks: &FieldLocks<Okay39>) {
+    let _guard1 = locks.a.lock();
+    let _guard2 = locks.b.lock();
+}
+
+struct Okay39b;
+fn okay39b(locks: &[Mutex<Okay39b>; 2]) {
+    let _guard1 = locks[0].lock();
+    let _guard2 = locks[1].lock();
+}
+
+struct RefFieldLocks<'a, T> {
+    a: &'a Mutex<T>,
+    b: &'a Mutex<T>,
+}
+
+// both fields can refer to the same lock
+struct Deadlock39b;
+fn deadlock39b(locks: &RefFieldLocks<'_, Deadlock39b>) {
+    let _guard1 = locks.a.lock();
+    let _guard2 = locks.b.lock();
+}
+
+// each box owns a different lock
+struct Okay39c;
+fn okay39c(locks: &(Box<Mutex<Okay39c>>, Box<Mutex<Okay39c>>)) {
+    let _guard1 = locks.0.lock();
+    let _guard2 = locks.1.lock();
+}
+
+// the guarded type is listed in `ignore_types`
+struct OkayIgnoredType;
+fn okay_ignored_type(mutex: &Mutex<OkayIgnoredType>) {
+    let _guard1 = mutex.lock();
+    let _guard2 = mutex.lock();
+}
+
+// with `analyze_deps` the lock call in the dependency is found
+fn deadlock_dep(counter: &Mutex<lock_dep::DepCounter>) {
+    let _guard = counter.lock();
+    lock_dep::increment(counter);
+}
+
+fn okay_dep(counter: &Mutex<lock_dep::DepCounter>) {
+    let guard = counter.lock();
+    drop(guard);
+    lock_dep::increment(counter);
+}
+
+// only reported with `--pessimistic`, since the function pointer can't be analysed
+struct PessimisticDeadlock;
+fn pessimistic_deadlock(release: fn(MutexGuard<PessimisticDeadlock>)) {
+    let mutex = Mutex::new(PessimisticDeadlock);
+    let guard1 = mutex.lock().unwrap();
+    release(guard1);
+    let _guard2 = mutex.lock();
+}
+
+struct ForbiddenIo;
+struct ForbiddenState;
+fn forbidden_order(io: &Mutex<ForbiddenIo>, state: &Mutex<ForbiddenState>) {
+    let _io = io.lock();
+    let _state = state.lock();
+}
+
+fn okay_forbidden_order(io: &Mutex<ForbiddenIo>, state: &Mutex<ForbiddenState>) {
+    let state_guard = state.lock();
+    drop(state_guard);
+    let _io = io.lock();
+}
+
+extern "C" {
+    fn abs(input: i32) -> i32;
+}
+
+// configured in `safe_pairs`, so the cycle is not reported
+struct SafePairA;
+struct SafePairB;
+fn safe_pair_order1(a: &Mutex<SafePairA>, b: &Mutex<SafePairB>) {
+    let _guard1 = a.lock();
+    let _guard2 = b.lock();
+}
+
+fn safe_pair_order2(a: &Mutex<SafePairA>, b: &Mutex<SafePairB>) {
+    let _guard1 = b.lock();
+    let _guard2 = a.lock();
+}
+
+struct OkayExtern;
+fn okay_extern() {
+    let mutex = Mutex::new(OkayExtern);
+    let _guard = mutex.lock();
+    unsafe {
+        abs(-3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    struct DeadlockTest;
+    #[test]
+    fn deadlock_test() {
+        let mutex = Mutex::new(DeadlockTest);
+        let _guard1 = mutex.lock();
+        let _guard2 = mutex.lock();
+    }
+}
+
 fn main() {}
@@ -0,0 +1,29 @@
+use std::sync::{Mutex, OnceLock};
+
+struct DeadlockOnceLockA;
+struct DeadlockOnceLockB;
+fn deadlock_once_lock(mutex: &Mutex<DeadlockOnceLockA>, once: &OnceLock<DeadlockOnceLockB>) {
+    let _guard = mutex.lock();
+    once.get_or_init(|| DeadlockOnceLockB);
+}
+
+fn deadlock_once_lock_init(mutex: &Mutex<DeadlockOnceLockA>, once: &OnceLock<DeadlockOnceLockB>) {
+    once.get_or_init(|| {
+        let _guard = mutex.lock();
+        DeadlockOnceLockB
+    });
+}
+
+struct DeadlockOnceLockReentrant;
+fn deadlock_once_lock_reentrant(once: &OnceLock<DeadlockOnceLockReentrant>) {
+    once.get_or_init(|| {
+        once.get_or_init(|| DeadlockOnceLockReentrant);
+        DeadlockOnceLockReentrant
+    });
+}
+
+struct OkayOnceLock;
+fn okay_once_lock(once: &OnceLock<OkayOnceLock>) {
+    once.get_or_init(|| OkayOnceLock);
+    once.get_or_init(|| OkayOnceLock);
+}
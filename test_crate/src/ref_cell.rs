@@ -0,0 +1,23 @@
+use std::cell::RefCell;
+use std::sync::Mutex;
+
+struct BorrowConflict;
+fn borrow_conflict(mutex: &Mutex<RefCell<BorrowConflict>>) {
+    let guard = mutex.lock().unwrap();
+    let _a = guard.borrow();
+    let _b = guard.borrow_mut();
+}
+
+struct BorrowMutConflict;
+fn borrow_mut_conflict(mutex: &Mutex<RefCell<BorrowMutConflict>>) {
+    let guard = mutex.lock().unwrap();
+    let _a = guard.borrow_mut();
+    let _b = guard.borrow_mut();
+}
+
+struct OkayBorrow;
+fn okay_borrow(mutex: &Mutex<RefCell<OkayBorrow>>) {
+    let guard = mutex.lock().unwrap();
+    let _a = guard.borrow();
+    let _b = guard.borrow();
+}
\ No newline at end of file
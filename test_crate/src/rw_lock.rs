@@ -0,0 +1,41 @@
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+struct DeadlockRwLock;
+fn deadlock_rw_lock(lock: &RwLock<DeadlockRwLock>) {
+    let _write_guard = lock.write();
+    let _read_guard = lock.read();
+}
+
+struct OkayRwLock;
+fn okay_rw_lock(lock: &RwLock<OkayRwLock>) {
+    let _read_guard1 = lock.read();
+    let _read_guard2 = lock.read();
+}
+
+// the function can't be analysed, but it returns a guard of the lock so the lock is still held
+struct DeadlockRwLockWriteReturned;
+fn deadlock_rw_lock_write_returned(
+    lock: &RwLock<DeadlockRwLockWriteReturned>,
+    keep: fn(RwLockWriteGuard<'_, DeadlockRwLockWriteReturned>) -> RwLockReadGuard<'_, DeadlockRwLockWriteReturned>,
+) {
+    let write_guard = lock.write().unwrap();
+    let _read_guard = keep(write_guard);
+    let _write_guard2 = lock.write();
+}
+
+struct DeadlockRwLockReadReturned;
+fn deadlock_rw_lock_read_returned(
+    lock: &RwLock<DeadlockRwLockReadReturned>,
+    keep: fn(RwLockReadGuard<'_, DeadlockRwLockReadReturned>) -> RwLockReadGuard<'_, DeadlockRwLockReadReturned>,
+) {
+    let read_guard = lock.read().unwrap();
+    let _read_guard = keep(read_guard);
+    let _write_guard = lock.write();
+}
+
+struct OkayRwLockDropped;
+fn okay_rw_lock_dropped(lock: &RwLock<OkayRwLockDropped>, release: fn(RwLockWriteGuard<'_, OkayRwLockDropped>)) {
+    let write_guard = lock.write().unwrap();
+    release(write_guard);
+    let _write_guard2 = lock.write();
+}
\ No newline at end of file
@@ -0,0 +1,44 @@
+use std::sync::Mutex;
+
+/// Runs a closure on the given value when dropped, similar to `scopeguard::guard`
+pub struct ScopeGuard<T, F: FnOnce(T)> {
+    value: Option<T>,
+    on_drop: Option<F>,
+}
+
+impl<T, F: FnOnce(T)> Drop for ScopeGuard<T, F> {
+    fn drop(&mut self) {
+        if let (Some(value), Some(on_drop)) = (self.value.take(), self.on_drop.take()) {
+            on_drop(value);
+        }
+    }
+}
+
+pub fn guard<T, F: FnOnce(T)>(value: T, on_drop: F) -> ScopeGuard<T, F> {
+    ScopeGuard {
+        value: Some(value),
+        on_drop: Some(on_drop),
+    }
+}
+
+struct OkayScopeGuard;
+fn okay_scope_guard() {
+    let mutex = Mutex::new(OkayScopeGuard);
+
+    {
+        let guard1 = mutex.lock();
+        let _defer = guard(guard1, drop);
+    }
+
+    let _guard2 = mutex.lock();
+}
+
+struct DeadlockScopeGuard;
+fn deadlock_scope_guard() {
+    let mutex = Mutex::new(DeadlockScopeGuard);
+
+    let guard1 = mutex.lock();
+    let _defer = guard(guard1, drop);
+
+    let _guard2 = mutex.lock();
+}
@@ -0,0 +1,111 @@
+use parking_lot::{Mutex, MutexGuard};
+
+/// Abstracts over lock types, lockcheck is configured with `trait_method` so every implementor is checked
+pub trait Lockable {
+    type Guard<'a> where Self: 'a;
+
+    fn lock_value(&self) -> Self::Guard<'_>;
+}
+
+pub struct TraitLock<T>(Mutex<T>);
+
+impl<T> TraitLock<T> {
+    pub fn new(value: T) -> Self {
+        TraitLock(Mutex::new(value))
+    }
+}
+
+impl<T> Lockable for TraitLock<T> {
+    type Guard<'a> = MutexGuard<'a, T> where T: 'a;
+
+    fn lock_value(&self) -> MutexGuard<'_, T> {
+        self.0.lock()
+    }
+}
+
+fn deadlock_trait_generic<L: Lockable>(lock: &L) {
+    let _guard1 = lock.lock_value();
+    let _guard2 = lock.lock_value();
+}
+
+struct DeadlockTraitConcrete;
+fn deadlock_trait_concrete(lock: &TraitLock<DeadlockTraitConcrete>) {
+    let _guard1 = lock.lock_value();
+    let _guard2 = lock.lock_value();
+}
+
+struct OkayTrait;
+fn okay_trait(lock: &TraitLock<OkayTrait>) {
+    let guard1 = lock.lock_value();
+    drop(guard1);
+    let _guard2 = lock.lock_value();
+}
+
+struct DeadlockDefaultFirst;
+struct DeadlockDefaultSecond;
+
+/// The default method locks in one order, and the implementor locks in the other order
+trait DeadlockDefaultMethod {
+    fn first(&self) -> &std::sync::Mutex<DeadlockDefaultFirst>;
+    fn second(&self) -> &std::sync::Mutex<DeadlockDefaultSecond>;
+    fn lock_both(&self);
+
+    fn lock_in_order(&self) {
+        let _first = self.first().lock();
+        let _second = self.second().lock();
+    }
+}
+
+struct DeadlockDefaultLocks {
+    first: std::sync::Mutex<DeadlockDefaultFirst>,
+    second: std::sync::Mutex<DeadlockDefaultSecond>,
+}
+
+impl DeadlockDefaultMethod for DeadlockDefaultLocks {
+    fn first(&self) -> &std::sync::Mutex<DeadlockDefaultFirst> {
+        &self.first
+    }
+
+    fn second(&self) -> &std::sync::Mutex<DeadlockDefaultSecond> {
+        &self.second
+    }
+
+    fn lock_both(&self) {
+        let _second = self.second.lock();
+        let _first = self.first.lock();
+    }
+}
+
+struct OkayDefaultFirst;
+struct OkayDefaultSecond;
+
+trait OkayDefaultMethod {
+    fn first(&self) -> &std::sync::Mutex<OkayDefaultFirst>;
+    fn second(&self) -> &std::sync::Mutex<OkayDefaultSecond>;
+    fn lock_both(&self);
+
+    fn lock_in_order(&self) {
+        let _first = self.first().lock();
+        let _second = self.second().lock();
+    }
+}
+
+struct OkayDefaultLocks {
+    first: std::sync::Mutex<OkayDefaultFirst>,
+    second: std::sync::Mutex<OkayDefaultSecond>,
+}
+
+impl OkayDefaultMethod for OkayDefaultLocks {
+    fn first(&self) -> &std::sync::Mutex<OkayDefaultFirst> {
+        &self.first
+    }
+
+    fn second(&self) -> &std::sync::Mutex<OkayDefaultSecond> {
+        &self.second
+    }
+
+    fn lock_both(&self) {
+        // the default method is called, so the locks are taken in the same order
+        self.lock_in_order();
+    }
+}
\ No newline at end of file